@@ -0,0 +1,55 @@
+use std::{fs, path};
+use std::io::Cursor;
+
+use criterion::{Criterion, criterion_group, criterion_main};
+use image::ImageDecoder;
+use image::webp::WebPDecoder;
+use image::webp::vp8::{Frame, Vp8Decoder};
+
+/// A 4096x4096 keyframe whose partitions are all zero bits, so that decoding is cheap next to
+/// the size of its planes.
+fn large_frame() -> Vec<u8> {
+    let (width, height) = (4096u16, 4096u16);
+    let tag = 2u32 << 5 | 1 << 4;
+    let mut data = vec![tag as u8, (tag >> 8) as u8, (tag >> 16) as u8, 0x9d, 0x01, 0x2a];
+    data.extend_from_slice(&[width as u8, (width >> 8) as u8, height as u8, (height >> 8) as u8]);
+    data.extend_from_slice(&[0; 4]);
+    data
+}
+
+fn decode_frame(c: &mut Criterion) {
+    let data = large_frame();
+    let mut group = c.benchmark_group("webp-vp8-frame");
+
+    group.bench_function("decode_frame", |b| b.iter(|| {
+        Vp8Decoder::new(Cursor::new(&data)).decode_frame().unwrap()
+    }));
+
+    let mut frame = Frame::default();
+    group.bench_function("decode_frame_into", |b| b.iter(|| {
+        Vp8Decoder::new(Cursor::new(&data)).decode_frame_into(&mut frame).unwrap();
+    }));
+}
+
+/// Compares decoding on its own with decoding into RGB or RGBA pixels.
+fn decode_discard(c: &mut Criterion) {
+    let mut group = c.benchmark_group("webp-decode");
+    let dir: path::PathBuf = [env!("CARGO_MANIFEST_DIR"), "tests", "images", "webp", "images"]
+        .iter()
+        .collect();
+
+    for file_name in &["simple-rgb.webp", "vp8x-rgb.webp"] {
+        let buf = fs::read(dir.join(file_name)).unwrap();
+        group.bench_function(format!("{}/decode_discard", file_name), |b| b.iter(|| {
+            WebPDecoder::new(Cursor::new(&buf)).unwrap().decode_discard().unwrap();
+        }));
+        group.bench_function(format!("{}/read_image", file_name), |b| b.iter(|| {
+            let decoder = WebPDecoder::new(Cursor::new(&buf)).unwrap();
+            let mut pixels = vec![0; decoder.total_bytes() as usize];
+            decoder.read_image(&mut pixels).unwrap();
+        }));
+    }
+}
+
+criterion_group!(benches, decode_frame, decode_discard);
+criterion_main!(benches);
@@ -23,7 +23,7 @@
 //! | BMP    | Yes | RGB(8), RGBA(8), Gray(8), GrayA(8) |
 //! | ICO    | Yes | Yes |
 //! | TIFF   | Baseline(no fax support) + LZW + PackBits | RGB(8), RGBA(8), Gray(8) |
-//! | WebP   | Lossy(Luma channel only) | No |
+//! | WebP   | Lossy | No |
 //! | PNM    | PBM, PGM, PPM, standard PAM | Yes |
 //! | DDS    | DXT1, DXT3, DXT5 | No |
 //! | TGA    | Yes | No |
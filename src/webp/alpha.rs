@@ -0,0 +1,180 @@
+//! Decoding of the alpha plane stored in `ALPH` chunks
+//!
+//! # Related Links
+//! * [Container specification](https://developers.google.com/speed/webp/docs/riff_container#alpha)
+
+use crate::error::{
+    DecodingError, ImageError, ImageResult, UnsupportedError, UnsupportedErrorKind,
+};
+use crate::image::ImageFormat;
+use crate::math::utils::clamp;
+
+/// The predictor applied to the alpha values before compression
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum FilterMethod {
+    None,
+    Horizontal,
+    Vertical,
+    Gradient,
+}
+
+/// Decodes the payload of an `ALPH` chunk into one alpha value per pixel.
+pub(crate) fn decode_alpha(data: &[u8], width: u16, height: u16) -> ImageResult<Vec<u8>> {
+    let header = match data.first() {
+        Some(&header) => header,
+        None => {
+            return Err(ImageError::Decoding(DecodingError::with_message(
+                ImageFormat::WebP.into(),
+                "Empty ALPH chunk".to_string(),
+            )))
+        }
+    };
+
+    // The upper bits are reserved and hint at the preprocessing, which needs no reversal.
+    let compression = header & 0b11;
+    let filtering = match (header >> 2) & 0b11 {
+        0 => FilterMethod::None,
+        1 => FilterMethod::Horizontal,
+        2 => FilterMethod::Vertical,
+        _ => FilterMethod::Gradient,
+    };
+
+    match compression {
+        0 => {}
+        1 => {
+            return Err(ImageError::Unsupported(
+                UnsupportedError::from_format_and_kind(
+                    ImageFormat::WebP.into(),
+                    UnsupportedErrorKind::GenericFeature(
+                        "Lossless compressed alpha is not supported".to_owned(),
+                    ),
+                ),
+            ))
+        }
+        method => {
+            return Err(ImageError::Decoding(DecodingError::with_message(
+                ImageFormat::WebP.into(),
+                format!("Invalid alpha compression method {}", method),
+            )))
+        }
+    }
+
+    let width = usize::from(width);
+    let size = width * usize::from(height);
+    if data.len() - 1 < size {
+        return Err(ImageError::Decoding(DecodingError::with_message(
+            ImageFormat::WebP.into(),
+            format!("ALPH chunk too short: {} of {} values", data.len() - 1, size),
+        )));
+    }
+
+    let mut alpha = data[1..1 + size].to_vec();
+    unfilter(filtering, &mut alpha, width);
+    Ok(alpha)
+}
+
+/// Reverses the filtering in place, row by row so that predictions use reconstructed values.
+fn unfilter(method: FilterMethod, alpha: &mut [u8], width: usize) {
+    if method == FilterMethod::None || width == 0 {
+        return;
+    }
+
+    for i in 0..alpha.len() {
+        let (x, y) = (i % width, i / width);
+
+        let predictor = match (method, x, y) {
+            (_, 0, 0) => 0,
+            // The left-most column is predicted from above and the top row from the left.
+            (FilterMethod::Horizontal, 0, _) | (FilterMethod::Gradient, 0, _) => alpha[i - width],
+            (FilterMethod::Vertical, _, 0) | (FilterMethod::Gradient, _, 0) => alpha[i - 1],
+            (FilterMethod::Horizontal, _, _) => alpha[i - 1],
+            (FilterMethod::Vertical, _, _) => alpha[i - width],
+            (FilterMethod::Gradient, _, _) => {
+                let left = i32::from(alpha[i - 1]);
+                let top = i32::from(alpha[i - width]);
+                let top_left = i32::from(alpha[i - width - 1]);
+                clamp(left + top - top_left, 0, 255) as u8
+            }
+            (FilterMethod::None, _, _) => 0,
+        };
+
+        alpha[i] = alpha[i].wrapping_add(predictor);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WIDTH: u16 = 3;
+    const HEIGHT: u16 = 3;
+
+    /// The payload of an `ALPH` chunk with the given filter and the filtered values. Some of the
+    /// reconstructed values wrap around.
+    fn filtered(method: FilterMethod) -> Vec<u8> {
+        let mut data = vec![(method as u8) << 2];
+        data.extend_from_slice(&[
+            10, 5, 250,
+            2, 1, 1,
+            255, 0, 3,
+        ]);
+        data
+    }
+
+    #[test]
+    fn test_unfiltered() {
+        let data = filtered(FilterMethod::None);
+        assert_eq!(decode_alpha(&data, WIDTH, HEIGHT).unwrap(), &data[1..]);
+    }
+
+    #[test]
+    fn test_horizontal_filter() {
+        // The top row is predicted from the left, the rest of the left column from above.
+        let data = filtered(FilterMethod::Horizontal);
+        assert_eq!(decode_alpha(&data, WIDTH, HEIGHT).unwrap(), [
+            10, 15, 9,
+            12, 13, 14,
+            11, 11, 14,
+        ]);
+    }
+
+    #[test]
+    fn test_vertical_filter() {
+        // The top row is predicted from the left, all other values from above.
+        let data = filtered(FilterMethod::Vertical);
+        assert_eq!(decode_alpha(&data, WIDTH, HEIGHT).unwrap(), [
+            10, 15, 9,
+            12, 16, 10,
+            11, 16, 13,
+        ]);
+    }
+
+    #[test]
+    fn test_gradient_filter() {
+        // As for the horizontal filter on the edges, then left + top - top left.
+        let data = filtered(FilterMethod::Gradient);
+        assert_eq!(decode_alpha(&data, WIDTH, HEIGHT).unwrap(), [
+            10, 15, 9,
+            12, 18, 13,
+            11, 17, 15,
+        ]);
+    }
+
+    #[test]
+    fn test_gradient_clamping() {
+        // The prediction of the last value is 250 + 250 - 0, clamped to 255.
+        let data = [3 << 2, 0, 250, 250, 5];
+        assert_eq!(decode_alpha(&data, 2, 2).unwrap(), [0, 250, 250, 4]);
+    }
+
+    #[test]
+    fn test_invalid_chunks() {
+        assert!(decode_alpha(&[], WIDTH, HEIGHT).is_err());
+        assert!(decode_alpha(&[0, 1, 2], WIDTH, HEIGHT).is_err());
+
+        match decode_alpha(&[1, 0, 0], 1, 1) {
+            Err(ImageError::Unsupported(_)) => {}
+            _ => panic!("expected lossless compressed alpha to be unsupported"),
+        }
+    }
+}
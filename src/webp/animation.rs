@@ -0,0 +1,320 @@
+//! Decoding and compositing of the frames of animated images
+//!
+//! Each frame of an animation is stored in an `ANMF` chunk, holding the position and duration of
+//! the frame followed by the chunks of its image data.
+//!
+//! # Related Links
+//! * [Container specification](https://developers.google.com/speed/webp/docs/riff_container#animation)
+
+use byteorder::{ByteOrder, LittleEndian};
+use std::cmp;
+use std::io::Cursor;
+
+use crate::color::Rgba;
+use crate::error::{
+    DecodingError, ImageError, ImageResult, UnsupportedError, UnsupportedErrorKind,
+};
+use crate::image::ImageFormat;
+use crate::math::Rect;
+use crate::RgbaImage;
+
+use super::alpha;
+use super::decoder::{check_first_partition, truncated};
+use super::vp8::{Frame, Vp8Decoder};
+use super::yuv::UpsampleFilter;
+
+/// The length of the header of an `ANMF` chunk, before the image data of the frame.
+pub(crate) const ANMF_HEADER_SIZE: usize = 16;
+
+/// The placement and timing of a frame on the canvas.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct FrameHeader {
+    /// The area of the canvas covered by the frame
+    pub(crate) rect: Rect,
+    /// The duration of the frame in milliseconds
+    pub(crate) duration: u32,
+    /// Whether the frame is alpha-blended onto the canvas instead of replacing it
+    pub(crate) blend: bool,
+    /// Whether the area of the frame is cleared before the next frame is drawn
+    pub(crate) dispose: bool,
+}
+
+/// The chunks of a frame, before they are decoded.
+pub(crate) struct FrameChunks<'a> {
+    pub(crate) header: FrameHeader,
+    pub(crate) alpha: Option<&'a [u8]>,
+    /// The contents of the `VP8 ` chunk
+    pub(crate) vp8: &'a [u8],
+}
+
+/// A decoded frame, with the same dimensions as its rectangle.
+pub(crate) struct AnimationFrame {
+    pub(crate) header: FrameHeader,
+    pub(crate) image: Frame,
+    pub(crate) alpha: Option<Vec<u8>>,
+}
+
+impl FrameHeader {
+    pub(crate) fn parse(data: &[u8]) -> ImageResult<FrameHeader> {
+        if data.len() < ANMF_HEADER_SIZE {
+            return Err(decoding_error(format!("ANMF chunk too short: {} bytes", data.len())));
+        }
+
+        // The offsets are stored divided by two, the dimensions minus one.
+        let flags = data[15];
+        Ok(FrameHeader {
+            rect: Rect {
+                x: 2 * LittleEndian::read_u24(&data[0..3]),
+                y: 2 * LittleEndian::read_u24(&data[3..6]),
+                width: LittleEndian::read_u24(&data[6..9]) + 1,
+                height: LittleEndian::read_u24(&data[9..12]) + 1,
+            },
+            duration: LittleEndian::read_u24(&data[12..15]),
+            blend: flags & 0b10 == 0,
+            dispose: flags & 0b01 != 0,
+        })
+    }
+}
+
+impl<'a> FrameChunks<'a> {
+    /// Splits the contents of an `ANMF` chunk into its header and image data.
+    ///
+    /// The `ALPH` chunk should precede the `VP8 ` chunk, but is found in either order.
+    pub(crate) fn parse(data: &'a [u8]) -> ImageResult<FrameChunks<'a>> {
+        let header = FrameHeader::parse(data)?;
+        let (mut alpha, mut vp8) = (None, None);
+        let mut rest = &data[ANMF_HEADER_SIZE..];
+
+        while rest.len() >= 8 {
+            let (fourcc, len) = (&rest[..4], LittleEndian::read_u32(&rest[4..8]));
+            let available = rest.len() - 8;
+            if u64::from(len) > available as u64 {
+                return Err(truncated(u64::from(len), available as u64));
+            }
+
+            let len = len as usize;
+            let contents = &rest[8..8 + len];
+            rest = &rest[cmp::min(8 + len + len % 2, rest.len())..];
+
+            match fourcc {
+                b"ALPH" if alpha.is_none() => alpha = Some(contents),
+                b"VP8 " if vp8.is_none() => vp8 = Some(contents),
+                b"VP8L" if vp8.is_none() => {
+                    return Err(ImageError::Unsupported(
+                        UnsupportedError::from_format_and_kind(
+                            ImageFormat::WebP.into(),
+                            UnsupportedErrorKind::GenericFeature(
+                                "Lossless WebP images are not supported".to_owned(),
+                            ),
+                        ),
+                    ))
+                }
+                _ => {}
+            }
+        }
+
+        match vp8 {
+            Some(vp8) => Ok(FrameChunks { header, alpha, vp8 }),
+            None => Err(decoding_error("ANMF chunk without image data".to_string())),
+        }
+    }
+
+    /// Returns whether the canvas after drawing this frame is independent of the earlier frames,
+    /// given the header of the previous frame, if any, and the size of the canvas.
+    ///
+    /// That is the case for the first frame, frames replacing the whole canvas and frames after
+    /// one covering the whole canvas that is disposed of.
+    pub(crate) fn is_key_frame(&self, previous: Option<&FrameHeader>, width: u32, height: u32) -> bool {
+        let covers_canvas = |rect: Rect| rect == Rect { x: 0, y: 0, width, height };
+        match previous {
+            None => true,
+            Some(previous) if previous.dispose && covers_canvas(previous.rect) => true,
+            Some(_) => {
+                covers_canvas(self.header.rect) && (!self.header.blend || self.alpha.is_none())
+            }
+        }
+    }
+
+    /// Decodes the image data, which must have the dimensions given in the header.
+    pub(crate) fn decode(&self) -> ImageResult<AnimationFrame> {
+        if self.vp8.len() >= 3 {
+            check_first_partition(self.vp8, self.vp8.len() as u32)?;
+        }
+
+        let mut vp8 = Vp8Decoder::new(Cursor::new(self.vp8));
+        let image = vp8.decode_frame()?;
+        image.check_planes()?;
+
+        let rect = self.header.rect;
+        if (u32::from(image.width), u32::from(image.height)) != (rect.width, rect.height) {
+            return Err(decoding_error(format!(
+                "Frame of {}x{} pixels in an ANMF chunk of {}x{}",
+                image.width, image.height, rect.width, rect.height
+            )));
+        }
+
+        let alpha = match self.alpha {
+            Some(data) => {
+                let alpha = alpha::decode_alpha(data, image.width, image.height)?;
+                debug_assert_eq!(
+                    alpha.len(),
+                    usize::from(image.width) * usize::from(image.height),
+                    "Alpha plane inconsistent with the frame",
+                );
+                Some(alpha)
+            }
+            None => None,
+        };
+
+        Ok(AnimationFrame { header: self.header, image, alpha })
+    }
+}
+
+impl AnimationFrame {
+    /// Returns the pixels of the frame as RGBA, opaque if it has no alpha plane.
+    fn rgba(&self, filter: UpsampleFilter) -> Vec<u8> {
+        let width = usize::from(self.image.width);
+        let pixels = width * usize::from(self.image.height);
+        let mut rgb = vec![0; 3 * pixels];
+        if width > 0 {
+            for (y, row) in rgb.chunks_mut(3 * width).enumerate() {
+                self.image.fill_rgb_row(y, row, filter);
+            }
+        }
+
+        let mut rgba = Vec::with_capacity(4 * pixels);
+        for (i, rgb) in rgb.chunks(3).enumerate() {
+            rgba.extend_from_slice(rgb);
+            rgba.push(self.alpha.as_ref().map_or(255, |alpha| alpha[i]));
+        }
+        rgba
+    }
+}
+
+/// The canvas frames are drawn on, which starts out filled with the background color.
+pub(crate) struct Canvas {
+    image: RgbaImage,
+    background: [u8; 4],
+    /// The area to fill with the background color before drawing the next frame
+    dispose: Option<Rect>,
+}
+
+impl Canvas {
+    pub(crate) fn new(width: u32, height: u32, background: [u8; 4]) -> Canvas {
+        let image = RgbaImage::from_pixel(width, height, Rgba(background));
+        Canvas { image, background, dispose: None }
+    }
+
+    /// Returns the current contents of the canvas.
+    pub(crate) fn image(&self) -> &RgbaImage {
+        &self.image
+    }
+
+    pub(crate) fn into_image(self) -> RgbaImage {
+        self.image
+    }
+
+    /// Draws the next frame, after disposing of the previous one if requested.
+    ///
+    /// The chroma planes of the frame are upsampled with `filter`.
+    pub(crate) fn draw(&mut self, frame: &AnimationFrame, filter: UpsampleFilter) -> ImageResult<()> {
+        let rect = frame.header.rect;
+        check_bounds(rect, self.image.width(), self.image.height())?;
+
+        if let Some(previous) = self.dispose.take() {
+            for y in previous.y..previous.y + previous.height {
+                for x in previous.x..previous.x + previous.width {
+                    self.image.get_pixel_mut(x, y).0 = self.background;
+                }
+            }
+        }
+
+        let rgba = frame.rgba(filter);
+        for (i, src) in rgba.chunks(4).enumerate() {
+            let (x, y) = (rect.x + i as u32 % rect.width, rect.y + i as u32 / rect.width);
+            let dst = &mut self.image.get_pixel_mut(x, y).0;
+            let src = [src[0], src[1], src[2], src[3]];
+            *dst = if frame.header.blend { blend(src, *dst) } else { src };
+        }
+
+        if frame.header.dispose {
+            self.dispose = Some(rect);
+        }
+
+        Ok(())
+    }
+}
+
+/// Checks that a frame covering `rect` fits on a canvas of `width` by `height` pixels.
+pub(crate) fn check_bounds(rect: Rect, width: u32, height: u32) -> ImageResult<()> {
+    if u64::from(rect.x) + u64::from(rect.width) > u64::from(width)
+        || u64::from(rect.y) + u64::from(rect.height) > u64::from(height)
+    {
+        return Err(decoding_error(format!(
+            "Frame of {}x{} pixels at ({}, {}) exceeds the canvas of {}x{}",
+            rect.width, rect.height, rect.x, rect.y, width, height
+        )));
+    }
+
+    Ok(())
+}
+
+/// Alpha-blends `src` over `dst`, both with straight alpha.
+///
+/// This uses the fixed point arithmetic of libwebp, with the alpha of the destination scaled
+/// by `(256 - src_alpha) / 256` and the color channels divided by the resulting alpha. Like in
+/// libwebp, opaque and fully transparent sources replace or keep the destination exactly.
+fn blend(src: [u8; 4], dst: [u8; 4]) -> [u8; 4] {
+    let src_alpha = u32::from(src[3]);
+    match src_alpha {
+        0 => return dst,
+        255 => return src,
+        _ => {}
+    }
+
+    let dst_factor = (u32::from(dst[3]) * (256 - src_alpha)) >> 8;
+    let alpha = src_alpha + dst_factor;
+    let scale = (1 << 24) / alpha;
+
+    let channel = |c: usize| {
+        let value = u32::from(src[c]) * src_alpha + u32::from(dst[c]) * dst_factor;
+        ((value * scale) >> 24) as u8
+    };
+
+    [channel(0), channel(1), channel(2), alpha as u8]
+}
+
+fn decoding_error(message: String) -> ImageError {
+    ImageError::Decoding(DecodingError::with_message(ImageFormat::WebP.into(), message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_header() {
+        let data = [5, 0, 0, 1, 0, 0, 99, 0, 0, 0, 1, 0, 0xe8, 0x03, 0, 0b11];
+        let header = FrameHeader::parse(&data).unwrap();
+
+        assert_eq!(header.rect, Rect { x: 10, y: 2, width: 100, height: 257 });
+        assert_eq!(header.duration, 1000);
+        assert!(!header.blend);
+        assert!(header.dispose);
+
+        assert!(FrameHeader::parse(&data[..15]).is_err());
+    }
+
+    #[test]
+    fn test_blend() {
+        // Opaque and fully transparent sources replace or keep the destination.
+        assert_eq!(blend([1, 2, 3, 255], [9, 9, 9, 200]), [1, 2, 3, 255]);
+        assert_eq!(blend([1, 2, 3, 0], [9, 9, 9, 200]), [9, 9, 9, 200]);
+
+        // Over a transparent destination the source is unchanged.
+        assert_eq!(blend([40, 80, 120, 128], [0, 0, 0, 0]), [40, 80, 120, 128]);
+
+        // Half of an opaque destination shows through.
+        assert_eq!(blend([200, 0, 0, 128], [0, 0, 200, 255]), [100, 0, 99, 255]);
+    }
+}
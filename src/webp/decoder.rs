@@ -1,155 +1,3613 @@
-use byteorder::{LittleEndian, ReadBytesExt};
+use byteorder::{ByteOrder, LittleEndian, ReadBytesExt};
+use std::ascii;
+use std::cmp;
 use std::convert::TryFrom;
 use std::default::Default;
+use std::error::Error;
+use std::fmt;
 use std::io::{self, Cursor, Read};
 use std::marker::PhantomData;
 use std::mem;
+use std::time::Duration;
 
-use crate::error::{DecodingError, ImageError, ImageResult};
-use crate::image::{ImageDecoder, ImageFormat};
+use crate::animation::{self, Delay, Frames};
+use crate::error::{
+    DecodingError, ImageError, ImageResult, LimitError, LimitErrorKind, ParameterError,
+    ParameterErrorKind, UnsupportedError, UnsupportedErrorKind,
+};
+use crate::image::{AnimationDecoder, GenericImage, ImageDecoder, ImageFormat};
+#[cfg(feature = "png")]
+use crate::image::ImageOutputFormat;
+use crate::math::Rect;
+use crate::utils::NonExhaustiveMarker;
+use crate::imageops::{self, FilterType};
+use crate::traits::Pixel;
+use crate::buffer::ConvertBuffer;
+use crate::{GrayAlphaImage, GrayImage, ImageBuffer, RgbImage, RgbaImage};
 
 use crate::color;
+use crate::dynimage::DynamicImage;
 
+use super::alpha;
+use super::animation::{
+    check_bounds, AnimationFrame, Canvas, FrameChunks, FrameHeader, ANMF_HEADER_SIZE,
+};
 use super::vp8::Frame;
 use super::vp8::Vp8Decoder;
+use super::yuv::{self, UpsampleFilter};
 
-/// WebP Image format decoder. Currently only supportes the luma channel (meaning that decoded
-/// images will be grayscale).
+/// The largest buffer reserved up front for the contents of a chunk.
+///
+/// Chunks are read with their length as capacity hint so that large chunks need no
+/// reallocations, but a corrupt length must not reserve memory that the file does not back.
+/// Longer chunks grow their buffer as usual beyond this size.
+const MAX_CHUNK_PREALLOCATION: u32 = 64 << 20;
+
+/// The largest width and height of the canvas accepted in `VP8X` chunks.
+const MAX_CANVAS_DIMENSION: u32 = 16384;
+
+/// The contents of the `VP8X` chunk of the extended file format.
+#[derive(Clone, Copy, Debug, Default)]
+struct ExtendedHeader {
+    animation: bool,
+    canvas_width: u32,
+    canvas_height: u32,
+}
+
+/// The image data found by walking the chunks of the file.
+enum ImageChunk {
+    /// A still image in a `VP8 ` chunk of the given length
+    Vp8(u32),
+    /// The frames of an animation, read into `WebPDecoder::frames`
+    Frames,
+}
+
+/// The reconstruction of an image failed after its dimensions were parsed.
+///
+/// This is the underlying error of the `DecodingError` returned in that case, available through
+/// `std::error::Error::source`, and carries the dimensions for diagnostics.
+#[derive(Debug)]
+pub struct FrameError {
+    width: u16,
+    height: u16,
+    source: ImageError,
+}
+
+impl FrameError {
+    /// Wraps errors of the reconstruction, unsupported features and limits are passed through.
+    fn wrap(width: u16, height: u16, err: ImageError) -> ImageError {
+        match err {
+            ImageError::Unsupported(_) | ImageError::Limits(_) => err,
+            source => ImageError::Decoding(DecodingError::new(
+                ImageFormat::WebP.into(),
+                FrameError { width, height, source },
+            )),
+        }
+    }
+
+    /// Returns the dimensions of the image that failed to decode.
+    pub fn dimensions(&self) -> (u32, u32) {
+        (u32::from(self.width), u32::from(self.height))
+    }
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Failed to decode {}x{} image: {}", self.width, self.height, self.source)
+    }
+}
+
+impl Error for FrameError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+/// The position of a tile yielded by `WebPDecoder::into_tiles`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TileCoord {
+    /// The column of the tile in the grid
+    pub column: u32,
+    /// The row of the tile in the grid
+    pub row: u32,
+    /// The horizontal offset of the tile in the image
+    pub x: u32,
+    /// The vertical offset of the tile in the image
+    pub y: u32,
+    /// The width of the tile, smaller than the tile width for the last column
+    pub width: u32,
+    /// The height of the tile, smaller than the tile height for the last row
+    pub height: u32,
+}
+
+/// The kind of image in a WebP file, as reported by `sniff` from the start of the file
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Features {
+    /// Whether the file is an animation, as flagged in the `VP8X` chunk
+    pub animated: bool,
+    /// Whether the image has an alpha channel
+    pub alpha: bool,
+    /// Whether the image is lossless, or `None` if the image chunk is not within the prefix
+    ///
+    /// Extended files may hold other chunks before the image data, and animation frames each
+    /// have their own compression, so this is only known for simple files and extended still
+    /// images whose image chunk directly follows the `VP8X` chunk.
+    pub lossless: Option<bool>,
+}
+
+/// Limits on the structure of the files accepted by the decoder
+///
+/// These bound the work done to parse the container, independently of the size of the image,
+/// and how much the files may deviate from the specification.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Limits {
+    /// The maximum number of chunks in the RIFF payload, `Limits::DEFAULT_MAX_CHUNKS` by default
+    ///
+    /// Every chunk counts, including `VP8X`, the image chunks, each frame of an animation and
+    /// unknown chunks that are skipped. Exceeding it is a decoding error.
+    pub max_chunks: u32,
+    /// Whether the first `DecodeWarning` is a decoding error, instead of being recorded for
+    /// `WebPDecoder::warnings`. Disabled by default.
+    ///
+    /// The warning is the source of the error. Unlike strict mode this rejects any deviation the
+    /// decoder detects, including those strict mode tolerates.
+    pub treat_warnings_as_errors: bool,
+    /// The maximum number of frames `WebPDecoder::collect_frames` holds in memory at once,
+    /// `Limits::DEFAULT_MAX_FRAMES` by default
+    ///
+    /// Each frame is a copy of the whole canvas. Exceeding it is a limit error, the frames can
+    /// still be decoded one at a time by `into_frames`.
+    pub max_frames: u32,
+}
+
+impl Limits {
+    /// The default maximum number of chunks, enough for animations of a million frames
+    pub const DEFAULT_MAX_CHUNKS: u32 = 1 << 20;
+    /// The default maximum number of frames decoded into memory at once
+    pub const DEFAULT_MAX_FRAMES: u32 = 1 << 10;
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_chunks: Limits::DEFAULT_MAX_CHUNKS,
+            treat_warnings_as_errors: false,
+            max_frames: Limits::DEFAULT_MAX_FRAMES,
+        }
+    }
+}
+
+/// A deviation from the specification that the decoder worked around
+///
+/// Warnings are recorded while the decoder is created and returned by `WebPDecoder::warnings`.
+/// They are decoding errors instead with `Limits::treat_warnings_as_errors`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DecodeWarning {
+    /// The `VP8X` chunk has reserved bits set, which are ignored
+    ReservedBits,
+    /// The animation flag is set but there are no animation frames, so the image is still
+    AnimationFlagWithoutFrames,
+    /// There are animation frames but the animation flag is not set
+    FramesWithoutAnimationFlag,
+    /// An image chunk was ignored, next to animation frames or after the image data
+    IgnoredImageChunk,
+    /// The canvas size of the `VP8X` chunk differs from the size of the still image, which is
+    /// decoded at its own size unless it is scaled by `WebPDecoder::set_scale_to_canvas`
+    CanvasSizeMismatch {
+        /// The canvas size of the `VP8X` chunk
+        canvas: (u32, u32),
+        /// The size of the image
+        image: (u32, u32),
+    },
+    /// The file ends before the end of its RIFF payload, after the image data
+    Truncated,
+    /// The `ALPH` chunk of a still image follows the `VP8 ` chunk instead of preceding it
+    AlphaAfterImage,
+    /// The `ICCP` chunk is empty, so the image has no usable ICC profile
+    EmptyIccProfile,
+    /// The image data is truncated or invalid partway through, so only the rows above are
+    /// decoded and the rest of the image is gray
+    PartialImage {
+        /// The number of rows of pixels at the top of the image that are decoded
+        rows: u32,
+    },
+    #[doc(hidden)]
+    /// Do not use this, not part of stability guarantees.
+    __NonExhaustive(NonExhaustiveMarker),
+}
+
+impl fmt::Display for DecodeWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodeWarning::ReservedBits => f.write_str("Reserved bits of the VP8X chunk are set"),
+            DecodeWarning::AnimationFlagWithoutFrames => {
+                f.write_str("Animation flag is set but the image contains no animation frames")
+            }
+            DecodeWarning::FramesWithoutAnimationFlag => {
+                f.write_str("Image contains animation frames but the animation flag is not set")
+            }
+            DecodeWarning::IgnoredImageChunk => f.write_str("Image contains an unused image chunk"),
+            DecodeWarning::AlphaAfterImage => f.write_str("ALPH chunk follows the image data"),
+            DecodeWarning::EmptyIccProfile => f.write_str("ICCP chunk is empty"),
+            DecodeWarning::PartialImage { rows } => {
+                write!(f, "Image data ends after {} rows of pixels", rows)
+            }
+            DecodeWarning::CanvasSizeMismatch { canvas, image } => write!(
+                f,
+                "Canvas of {}x{} pixels for an image of {}x{}",
+                canvas.0, canvas.1, image.0, image.1
+            ),
+            DecodeWarning::Truncated => f.write_str("File ends before the end of its RIFF payload"),
+            DecodeWarning::__NonExhaustive(marker) => match marker._private {},
+        }
+    }
+}
+
+impl Error for DecodeWarning {}
+
+/// The rounding of the color channels when they are premultiplied by alpha
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PremultiplyRounding {
+    /// Rounds down, computing `c * a / 255` with integer division
+    Truncate,
+    /// Rounds to the nearest value, computing `(c * a + 127) / 255` with integer division
+    ///
+    /// As 255 is odd, `c * a / 255` is never exactly halfway between two integers.
+    Nearest,
+}
+
+impl Default for PremultiplyRounding {
+    fn default() -> Self {
+        PremultiplyRounding::Nearest
+    }
+}
+
+impl PremultiplyRounding {
+    fn premultiply(self, channel: u8, alpha: u8) -> u8 {
+        let product = u16::from(channel) * u16::from(alpha);
+        let rounded = match self {
+            PremultiplyRounding::Truncate => product / 255,
+            PremultiplyRounding::Nearest => (product + 127) / 255,
+        };
+        rounded as u8
+    }
+}
+
+/// WebP Image format decoder. Currently only supports lossy images, which are decoded to RGB,
+/// or to RGBA if they have an uncompressed alpha plane.
+///
+/// The dimensions and color type are determined when the decoder is created, so that
+/// `total_bytes` can be used to size the buffer passed to `read_image`.
+///
+/// Animations have the dimensions of their canvas and `read_image` decodes the first frame as
+/// composited on it. All frames are available through the `AnimationDecoder` trait.
+///
+/// The whole file is read when the decoder is created, leaving the reader right after the RIFF
+/// payload. To keep using a reader after the consuming methods such as `read_image`, pass it by
+/// mutable reference, as `&mut R` implements `Read` too:
+///
+/// ```no_run
+/// # use image::ImageDecoder;
+/// # use image::webp::WebPDecoder;
+/// # use std::io::Read;
+/// # fn main() -> image::ImageResult<()> {
+/// let mut file = std::fs::File::open("image.webp")?;
+/// let decoder = WebPDecoder::new(&mut file)?;
+/// let mut image = vec![0; decoder.total_bytes() as usize];
+/// decoder.read_image(&mut image)?;
+///
+/// // The data following the image is still available.
+/// let mut trailer = Vec::new();
+/// file.read_to_end(&mut trailer)?;
+/// # Ok(())
+/// # }
+/// ```
 pub struct WebPDecoder<R> {
     r: R,
     frame: Frame,
     have_frame: bool,
+    strict: bool,
+    extended: Option<ExtendedHeader>,
+    alpha_chunk: Option<Vec<u8>>,
+    alpha: Option<Vec<u8>>,
+    /// The size of the RIFF payload after the `WEBP` signature
+    riff_size: u64,
+    /// The number of bytes of the RIFF payload that have not been read yet
+    riff_remaining: u64,
+    limits: Limits,
+    /// The number of chunks of the RIFF payload that have been read
+    chunks: u32,
+    warnings: Vec<DecodeWarning>,
+    /// The mean and standard deviation of each color channel for `read_image_f32`
+    normalization: Option<([f32; 3], [f32; 3])>,
+    premultiply: bool,
+    rounding: PremultiplyRounding,
+    /// The contents of the `ANMF` chunks of an animation
+    frames: Vec<Vec<u8>>,
+    /// The background color of the `ANIM` chunk, as RGBA
+    background: Option<[u8; 4]>,
+    background_override: Option<[u8; 4]>,
+    /// The number of times an animation is played from the `ANIM` chunk, zero for endlessly
+    loop_count: Option<u16>,
+    min_frame_duration: u32,
+    scale_to_canvas: bool,
+    /// The contents of a `VP8 ` chunk next to the frames of an animation
+    default_image: Option<Vec<u8>>,
+    /// The first frame of an animation, composited on the canvas when the image is read
+    first_frame: Option<AnimationFrame>,
+    upsampling: UpsampleFilter,
+    icc_profile: Option<Vec<u8>>,
+}
+
+/// Decodes a WebP image held in memory.
+///
+/// This is a shorthand for `DynamicImage::from_decoder(WebPDecoder::new(data)?)`. Data that does
+/// not start with a WebP signature is rejected with a decoding error. There is no zero-copy path
+/// for slices: the compressed image data is copied into the partition buffers of the VP8 decoder
+/// as for any other reader.
+pub fn decode(data: &[u8]) -> ImageResult<DynamicImage> {
+    DynamicImage::from_decoder(WebPDecoder::new(data)?)
+}
+
+/// Decodes a WebP image held in memory and encodes it as PNG.
+///
+/// The image is decoded as by `decode`, so that images with alpha and animations, of which the
+/// first frame is transcoded, become RGBA PNG files and other images RGB ones.
+#[cfg(feature = "png")]
+pub fn transcode_to_png(data: &[u8]) -> ImageResult<Vec<u8>> {
+    let mut png = Vec::new();
+    decode(data)?.write_to(&mut png, ImageOutputFormat::Png)?;
+    Ok(png)
+}
+
+/// Reports the kind of image in a WebP file from a prefix of it, without creating a decoder.
+///
+/// This inspects the first chunk, which for extended files is the `VP8X` chunk with the
+/// animation and alpha flags. 30 bytes are enough to get all flags, and 34 to see the image
+/// chunk of extended still images without any chunks in between. Returns `None` if the prefix
+/// does not start with a WebP signature or ends before the first chunk can be interpreted.
+pub fn sniff(prefix: &[u8]) -> Option<Features> {
+    if prefix.len() < 21 || &prefix[..4] != b"RIFF" || &prefix[8..12] != b"WEBP" {
+        return None;
+    }
+
+    match &prefix[12..16] {
+        b"VP8 " => Some(Features { animated: false, alpha: false, lossless: Some(false) }),
+        b"VP8L" => {
+            // The alpha hint follows the signature byte and the dimensions in 14 bits each.
+            let bits = prefix.get(21..25).map(LittleEndian::read_u32)?;
+            Some(Features { animated: false, alpha: bits & (1 << 28) != 0, lossless: Some(true) })
+        }
+        b"VP8X" => {
+            let flags = prefix[20];
+            let animated = flags & 0b0000_0010 != 0;
+            let lossless = match prefix.get(30..34) {
+                Some(chunk) if !animated && chunk == b"VP8L" => Some(true),
+                Some(chunk) if !animated && (chunk == b"VP8 " || chunk == b"ALPH") => Some(false),
+                _ => None,
+            };
+            Some(Features { animated, alpha: flags & 0b0001_0000 != 0, lossless })
+        }
+        _ => None,
+    }
 }
 
 impl<R: Read> WebPDecoder<R> {
     /// Create a new WebPDecoder from the Reader ```r```.
-    /// This function takes ownership of the Reader.
+    /// This function takes ownership of the Reader, which may be a `&mut R` to keep it.
+    ///
+    /// Strict mode is disabled, see `with_strictness` for the files this tolerates.
     pub fn new(r: R) -> ImageResult<WebPDecoder<R>> {
+        WebPDecoder::with_strictness(r, false)
+    }
+
+    /// Create a new WebPDecoder from the Reader ```r```.
+    ///
+    /// `strict` enables strict mode, which rejects files whose `VP8X` flags contradict their
+    /// content instead of trying to make sense of them. The animation flag is reconciled with
+    /// the presence of `ANMF` chunks as follows:
+    ///
+    /// | Animation flag | `ANMF` chunk | Non-strict mode | Strict mode    |
+    /// | -------------- | ------------ | --------------- | -------------- |
+    /// | Set            | Present      | Animation       | Animation      |
+    /// | Set            | Absent       | Still image     | Decoding error |
+    /// | Unset          | Present      | Animation       | Decoding error |
+    /// | Unset          | Absent       | Still image     | Still image    |
+    ///
+    /// A file without a `VP8X` chunk is treated as if the animation flag was unset.
+    ///
+    /// A still image consists of exactly one `VP8 ` or `VP8L` chunk. In strict mode the chunks
+    /// after it are checked as well and a file containing both is a decoding error, while
+    /// non-strict mode uses the first one and ignores any others. Likewise image chunks next to
+    /// the `ANMF` chunks of an animation are rejected in strict mode and ignored otherwise. A
+    /// still image whose size differs from the canvas size of the `VP8X` chunk is rejected in
+    /// strict mode as well, see `set_scale_to_canvas`.
+    ///
+    /// The image data of a truncated still image is an error in strict mode. Otherwise the rows
+    /// decoded before the data ends are kept with the rest of the image filled in gray, as
+    /// recorded by `DecodeWarning::PartialImage`, if there are any.
+    pub fn with_strictness(r: R, strict: bool) -> ImageResult<WebPDecoder<R>> {
+        WebPDecoder::with_limits(r, strict, Limits::default())
+    }
+
+    /// Create a new WebPDecoder from the Reader ```r``` that enforces the given limits.
+    ///
+    /// `strict` enables strict mode as described for `with_strictness`.
+    pub fn with_limits(r: R, strict: bool, limits: Limits) -> ImageResult<WebPDecoder<R>> {
+        let mut decoder = WebPDecoder::unread(r, strict, limits);
+        decoder.read_metadata()?;
+        Ok(decoder)
+    }
+
+    /// Creates a decoder that has not read anything yet.
+    fn unread(r: R, strict: bool, limits: Limits) -> WebPDecoder<R> {
         let f: Frame = Default::default();
 
-        let mut decoder = WebPDecoder {
+        WebPDecoder {
             r,
             have_frame: false,
             frame: f,
-        };
-        decoder.read_metadata()?;
-        Ok(decoder)
+            strict,
+            extended: None,
+            alpha_chunk: None,
+            alpha: None,
+            riff_size: 0,
+            riff_remaining: 0,
+            limits,
+            chunks: 0,
+            warnings: Vec::new(),
+            normalization: None,
+            premultiply: false,
+            rounding: PremultiplyRounding::default(),
+            frames: Vec::new(),
+            background: None,
+            background_override: None,
+            loop_count: None,
+            min_frame_duration: 0,
+            scale_to_canvas: false,
+            default_image: None,
+            first_frame: None,
+            upsampling: UpsampleFilter::default(),
+            icc_profile: None,
+        }
     }
 
-    fn read_riff_header(&mut self) -> ImageResult<u32> {
-        let mut riff = Vec::with_capacity(4);
-        self.r.by_ref().take(4).read_to_end(&mut riff)?;
-        let size = self.r.read_u32::<LittleEndian>()?;
-        let mut webp = Vec::with_capacity(4);
-        self.r.by_ref().take(4).read_to_end(&mut webp)?;
+    /// Reads the dimensions of the image in `r`, without decoding it.
+    ///
+    /// This only parses the RIFF header and the chunks up to the canvas size of the `VP8X`
+    /// chunk or, in simple files, the dimensions in the header of the `VP8 ` or `VP8L` chunk.
+    /// The dimensions of lossless images are read even though decoding them is not supported.
+    pub fn read_dimensions(r: R) -> ImageResult<(u32, u32)> {
+        let mut decoder = WebPDecoder::unread(r, false, Limits::default());
+        decoder.read_riff_header()?;
 
-        if &*riff != b"RIFF" {
-            return Err(ImageError::Decoding(DecodingError::with_message(
-                ImageFormat::WebP.into(),
-                "Invalid RIFF signature".to_string(),
-            )));
+        loop {
+            if decoder.riff_remaining < 8 {
+                return Err(ImageError::Decoding(DecodingError::with_message(
+                    ImageFormat::WebP.into(),
+                    "No image data in the RIFF payload".to_string(),
+                )));
+            }
+
+            let chunk = read_field(&mut decoder.r, "chunk fourcc")?;
+            let len = read_length(&mut decoder.r, "chunk length")?;
+            decoder.consume(8);
+
+            match &chunk {
+                b"VP8X" => {
+                    decoder.read_extended_header(len)?;
+                    let extended = decoder.extended.unwrap_or_default();
+                    return Ok((extended.canvas_width, extended.canvas_height));
+                }
+                b"VP8 " => {
+                    let mut header = [0u8; 10];
+                    decoder.r.read_exact(&mut header)?;
+                    let (width, height) = keyframe_dimensions(&header)?;
+                    return Ok((u32::from(width), u32::from(height)));
+                }
+                b"VP8L" => {
+                    // A signature byte followed by the dimensions minus one in 14 bits each
+                    let signature = decoder.r.read_u8()?;
+                    if signature != 0x2f {
+                        return Err(ImageError::Decoding(DecodingError::with_message(
+                            ImageFormat::WebP.into(),
+                            format!("Invalid VP8L signature {:#x}", signature),
+                        )));
+                    }
+
+                    let bits = decoder.r.read_u32::<LittleEndian>()?;
+                    return Ok(((bits & 0x3FFF) + 1, ((bits >> 14) & 0x3FFF) + 1));
+                }
+                _ => {
+                    decoder.skip_contents(len)?;
+                }
+            }
+        }
+    }
+
+    /// Lists the chunks of the WebP file in `r` for debugging, without decoding the image.
+    ///
+    /// Returns one line per chunk with its fourcc, its offset from the start of the file and
+    /// the length of its contents. The flags and canvas size of `VP8X` chunks and the placement
+    /// of animation frames are decoded, and the chunks within each `ANMF` chunk are listed
+    /// indented below it. An error after the RIFF header ends the listing with a line describing
+    /// it, so that the chunks before the problem are still shown.
+    pub fn dump_structure(r: R) -> ImageResult<String> {
+        let mut decoder = WebPDecoder::unread(r, false, Limits::default());
+        let size = decoder.read_riff_header()?;
+        let mut dump = format!("RIFF WEBP, {} bytes\n", size);
+
+        while decoder.riff_remaining >= 8 {
+            let offset = 12 + decoder.riff_size - decoder.riff_remaining;
+            match decoder.count_chunk().and_then(|_| decoder.read_chunk_for_dump()) {
+                Ok((fourcc, len, data)) => {
+                    dump += &format!("{} at {}, {} bytes", display_fourcc(&fourcc), offset, len);
+                    match (&fourcc, data) {
+                        (b"VP8X", Some(data)) => dump += &describe_extended_header(&data),
+                        (b"ANMF", Some(data)) => dump += &describe_frame(offset + 8, &data),
+                        _ => dump.push('\n'),
+                    }
+                }
+                Err(err) => {
+                    dump += &format!("error at {}: {}\n", offset, err);
+                    break;
+                }
+            }
         }
 
-        if &*webp != b"WEBP" {
+        Ok(dump)
+    }
+
+    /// Checks that `r` contains a structurally valid WebP image, without decoding it.
+    ///
+    /// This checks the RIFF and WebP signatures, walks the chunks in strict mode so that
+    /// inconsistent `VP8X` flags, a second image chunk and a canvas of another size than the
+    /// image are rejected, and checks the frame header of the image data and that it is not
+    /// truncated. The pixels are not reconstructed, so this is much cheaper than
+    /// decoding but does not detect corrupted image data.
+    pub fn validate(r: R) -> ImageResult<()> {
+        let mut decoder = WebPDecoder::unread(r, true, Limits::default());
+
+        decoder.read_riff_header()?;
+        let len = match decoder.read_vp8_header()? {
+            ImageChunk::Vp8(len) => len,
+            ImageChunk::Frames => {
+                let extended = decoder.extended.unwrap_or_default();
+                for data in &decoder.frames {
+                    let frame = FrameChunks::parse(data)?;
+                    keyframe_dimensions(frame.vp8)?;
+                    check_first_partition(frame.vp8, frame.vp8.len() as u32)?;
+                    check_bounds(frame.header.rect, extended.canvas_width, extended.canvas_height)?;
+                }
+                return Ok(());
+            }
+        };
+
+        // The frame tag, start code and dimensions of a keyframe, Section 9.1
+        let mut header = [0u8; 10];
+        if len < header.len() as u32 {
             return Err(ImageError::Decoding(DecodingError::with_message(
                 ImageFormat::WebP.into(),
-                "Invalid WEBP signature".to_string(),
+                format!("VP8 chunk too short: {} bytes", len),
             )));
         }
+        decoder.r.read_exact(&mut header)?;
+        let (width, height) = keyframe_dimensions(&header)?;
+        check_first_partition(&header, len)?;
+        decoder.check_canvas_size(width, height)?;
 
-        Ok(size)
+        let remaining = u64::from(len) - header.len() as u64;
+        let copied = io::copy(&mut decoder.r.by_ref().take(remaining), &mut io::sink())?;
+        if copied != remaining {
+            return Err(truncated(u64::from(len), header.len() as u64 + copied));
+        }
+        let padding = io::copy(&mut decoder.r.by_ref().take(u64::from(len % 2)), &mut io::sink())?;
+        decoder.consume(u64::from(len) + padding);
+
+        // The chunks after the image data are checked as when decoding, for a second image.
+        decoder.skip_trailing_chunks(false)
     }
 
-    fn read_vp8_header(&mut self) -> ImageResult<u32> {
-        loop {
-            let mut chunk = Vec::with_capacity(4);
-            self.r.by_ref().take(4).read_to_end(&mut chunk)?;
+    /// Returns the version of the VP8 bitstream.
+    ///
+    /// The version selects the filters used to reconstruct the image, versions 2 and 3 are
+    /// decoded without the loop filter.
+    pub fn vp8_profile(&self) -> u8 {
+        match self.first_frame {
+            Some(ref frame) => frame.image.version,
+            None => self.frame.version,
+        }
+    }
 
-            match &*chunk {
-                b"VP8 " => {
-                    let len = self.r.read_u32::<LittleEndian>()?;
-                    return Ok(len);
-                }
-                b"ALPH" | b"VP8L" | b"ANIM" | b"ANMF" => {
-                    // Alpha, Lossless and Animation isn't supported
-                    return Err(ImageError::Decoding(DecodingError::with_message(
-                        ImageFormat::WebP.into(),
-                        "Unsupported WEBP feature.".to_string(),
-                    )));
-                }
-                _ => {
-                    let mut len = self.r.read_u32::<LittleEndian>()?;
-                    if len % 2 != 0 {
-                        // RIFF chunks containing an uneven number of bytes append
-                        // an extra 0x00 at the end of the chunk
-                        len += 1;
-                    }
-                    io::copy(&mut self.r.by_ref().take(len as u64), &mut io::sink())?;
+    /// Returns the ICC profile of the `ICCP` chunk, if the image has one.
+    ///
+    /// An empty `ICCP` chunk is no usable profile, so this returns `None` for it outside of
+    /// strict mode, where it is an error.
+    pub fn icc_profile(&self) -> Option<&[u8]> {
+        self.icc_profile.as_ref().map(|profile| &profile[..])
+    }
+
+    /// Returns whether the image has an ICC profile describing its color space.
+    ///
+    /// The profile is never applied: the pixels are returned as stored and any color management
+    /// is left to the caller, with the profile returned by `icc_profile`. Images without a
+    /// profile are in sRGB.
+    pub fn is_color_managed(&self) -> bool {
+        self.icc_profile.is_some()
+    }
+
+    /// Returns whether the decoded image fits into a buffer of `max_bytes`, as passed to
+    /// `read_image`.
+    ///
+    /// The decoded size is `total_bytes()`, with 3 bytes per pixel for RGB output and 4 for
+    /// images with alpha and animations. As the image data is decoded when the decoder is
+    /// created, rejecting images before that work uses `read_dimensions` instead, assuming 4
+    /// bytes per pixel unless `sniff` reports an image without alpha.
+    pub fn fits_in(&self, max_bytes: u64) -> bool {
+        self.total_bytes() <= max_bytes
+    }
+
+    /// Returns the number of bytes read from the reader, including the RIFF header.
+    ///
+    /// The decoder reads the whole RIFF payload, so this is the length of the WebP file unless it
+    /// ends before its declared size. Data following it in the reader is left untouched.
+    pub fn bytes_consumed(&self) -> u64 {
+        12 + self.riff_size - self.riff_remaining
+    }
+
+    /// Returns the deviations from the specification found while reading the image, in order.
+    pub fn warnings(&self) -> &[DecodeWarning] {
+        &self.warnings
+    }
+
+    /// Sets whether the color channels of images with an alpha plane are premultiplied by alpha.
+    ///
+    /// Images are decoded with straight alpha by default. Premultiplication applies to all
+    /// decoding methods that output alpha, and has no effect on images without alpha.
+    pub fn set_premultiplied_alpha(&mut self, premultiply: bool) {
+        self.premultiply = premultiply;
+    }
+
+    /// Sets how premultiplied color channels are rounded, to the nearest value by default.
+    pub fn set_premultiply_rounding(&mut self, rounding: PremultiplyRounding) {
+        self.rounding = rounding;
+    }
+
+    /// Sets the color of the canvas of animations, overriding the background of the file.
+    ///
+    /// The canvas is filled with the background color before the first frame is drawn, and so
+    /// is the area of frames disposed of after they were shown. `None` restores the color of the
+    /// `ANIM` chunk, stored as BGRA in the file but given here as RGBA, or transparent black if
+    /// the file has none. Still images are not affected.
+    pub fn set_background_color(&mut self, color: Option<[u8; 4]>) {
+        self.background_override = color;
+    }
+
+    /// Sets the shortest duration of animation frames in milliseconds, for files with
+    /// pathological timings.
+    ///
+    /// Frames with a shorter duration are shown for `ms` instead, by `into_frames`,
+    /// `into_frames_reversed`, `into_atlas` and `frame_at_time`. Encoders often write durations
+    /// of zero or a few milliseconds, which players such as browsers raise to a minimum. By
+    /// default the durations are used as stored, so frames of zero duration advance instantly.
+    pub fn set_min_frame_duration(&mut self, ms: u32) {
+        self.min_frame_duration = ms;
+    }
+
+    /// Sets whether a still image is scaled to the canvas size of the `VP8X` chunk if the sizes
+    /// differ. Disabled by default.
+    ///
+    /// The specification requires the canvas to have the size of the image, but some encoders
+    /// write images meant to be scaled to the canvas, which libwebp does in some cases. Without
+    /// scaling such images are decoded at their own size with `DecodeWarning::CanvasSizeMismatch`,
+    /// and are an error in strict mode, so this only applies outside of it. The image is
+    /// resampled with `FilterType::Triangle`, and `dimensions` and all outputs other than the
+    /// planes of `into_yuv420` and `into_yuv444` have the canvas size.
+    pub fn set_scale_to_canvas(&mut self, scale: bool) {
+        self.scale_to_canvas = scale;
+    }
+
+    /// Returns the canvas size if the still image is scaled to it.
+    fn scaled_size(&self) -> Option<(u32, u32)> {
+        match self.extended {
+            Some(extended) if self.scale_to_canvas && self.frames.is_empty() => {
+                let canvas = (extended.canvas_width, extended.canvas_height);
+                let image = (u32::from(self.frame.width), u32::from(self.frame.height));
+                if canvas != image {
+                    Some(canvas)
+                } else {
+                    None
                 }
             }
+            _ => None,
         }
     }
 
-    fn read_frame(&mut self, len: u32) -> ImageResult<()> {
-        let mut framedata = Vec::new();
-        self.r.by_ref().take(len as u64).read_to_end(&mut framedata)?;
-        let m = io::Cursor::new(framedata);
+    /// Returns the background color of the canvas of animations, as RGBA.
+    fn background_color(&self) -> [u8; 4] {
+        self.background_override.or(self.background).unwrap_or([0; 4])
+    }
+
+    /// Sets the filter used to upsample the chroma planes for RGB output.
+    ///
+    /// Images are upsampled with `UpsampleFilter::Point` by default. The filter applies to all
+    /// decoding methods, including the frames of animations.
+    pub fn set_upsampling(&mut self, filter: UpsampleFilter) {
+        self.upsampling = filter;
+    }
 
-        let mut v = Vp8Decoder::new(m);
-        let frame = v.decode_frame()?;
+    /// Decodes the image into `buf` with 16 bits per channel.
+    ///
+    /// Each 8-bit sample `v` is widened to `v * 257`, which maps `0` to `0` and `255` to
+    /// `u16::max_value()`. The original sample is recovered exactly as `v / 257` or `v >> 8`.
+    ///
+    /// The samples are in the same order as for `read_image`, so `buf` must have room for
+    /// `total_bytes()` samples. Samples past those are left untouched.
+    pub fn read_image_u16(self, buf: &mut [u16]) -> ImageResult<()> {
+        if !self.fits_in(buf.len() as u64) {
+            return Err(buffer_too_small());
+        }
 
-        self.frame = frame.clone();
+        let mut data = vec![0; self.total_bytes() as usize];
+        self.fill_image(&mut data);
+        for (wide, &narrow) in buf.iter_mut().zip(&data) {
+            *wide = u16::from(narrow) * 257;
+        }
 
         Ok(())
     }
 
-    fn read_metadata(&mut self) -> ImageResult<()> {
-        if !self.have_frame {
-            self.read_riff_header()?;
-            let len = self.read_vp8_header()?;
-            self.read_frame(len)?;
+    /// Decodes the image into `buf` as RGBA with 16 bits per channel, such as for color
+    /// management with the profile of `icc_profile`.
+    ///
+    /// All samples, alpha included, are widened as by `read_image_u16`, which adds no precision
+    /// to the 8-bit samples of WebP. Images without alpha are returned as opaque and animations
+    /// as their first frame on the canvas. `buf` must have room for four samples per pixel.
+    pub fn read_image_rgba16(self, buf: &mut [u16]) -> ImageResult<()> {
+        let (width, height) = self.dimensions();
+        if (buf.len() as u64) < 4 * u64::from(width) * u64::from(height) {
+            return Err(buffer_too_small());
+        }
 
-            self.have_frame = true;
+        let rgba = self.decode_rgba()?;
+        for (wide, &narrow) in buf.iter_mut().zip(rgba.iter()) {
+            *wide = u16::from(narrow) * 257;
         }
 
         Ok(())
     }
-}
 
-/// Wrapper struct around a `Cursor<Vec<u8>>`
-pub struct WebpReader<R>(Cursor<Vec<u8>>, PhantomData<R>);
-impl<R> Read for WebpReader<R> {
-    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        self.0.read(buf)
+    /// Normalizes the samples of `read_image_f32` per color channel.
+    ///
+    /// Each red, green and blue sample `v` is output as `(v - mean[c]) / std[c]` for its channel
+    /// `c`, where `v` is the sample scaled to `[0, 1]`. Alpha samples are not normalized.
+    ///
+    /// # Panics
+    ///
+    /// Panics if any standard deviation is zero.
+    pub fn set_normalization(&mut self, mean: [f32; 3], std: [f32; 3]) {
+        assert!(std.iter().all(|&std| std != 0.0), "standard deviation must not be zero");
+        self.normalization = Some((mean, std));
     }
-    fn read_to_end(&mut self, buf: &mut Vec<u8>) -> io::Result<usize> {
-        if self.0.position() == 0 && buf.is_empty() {
-            mem::swap(buf, self.0.get_mut());
-            Ok(buf.len())
-        } else {
-            self.0.read_to_end(buf)
+
+    /// Decodes the image into `buf` with floating point samples.
+    ///
+    /// Each 8-bit sample `v` is scaled to `v / 255.0` and then normalized if `set_normalization`
+    /// was called. The samples are not linearized, both operations apply to the sRGB encoded
+    /// values. The samples are in the same order as for `read_image`, so `buf` must have room
+    /// for `total_bytes()` samples. Samples past those are left untouched.
+    pub fn read_image_f32(self, buf: &mut [f32]) -> ImageResult<()> {
+        if !self.fits_in(buf.len() as u64) {
+            return Err(buffer_too_small());
         }
+
+        // Precompute the output for every sample value, so that each sample is mapped once.
+        let mut tables = [[0f32; 256]; 4];
+        for (c, table) in tables.iter_mut().enumerate() {
+            for (v, value) in table.iter_mut().enumerate() {
+                *value = match self.normalization {
+                    Some((mean, std)) if c < 3 => (v as f32 / 255.0 - mean[c]) / std[c],
+                    _ => v as f32 / 255.0,
+                };
+            }
+        }
+
+        let channels = usize::from(self.color_type().channel_count());
+        let mut data = vec![0; self.total_bytes() as usize];
+        self.fill_image(&mut data);
+
+        for (pixel, samples) in buf.chunks_mut(channels).zip(data.chunks(channels)) {
+            for (c, (value, &sample)) in pixel.iter_mut().zip(samples).enumerate() {
+                *value = tables[c][usize::from(sample)];
+            }
+        }
+
+        Ok(())
     }
-}
 
-impl<'a, R: 'a + Read> ImageDecoder<'a> for WebPDecoder<R> {
-    type Reader = WebpReader<R>;
+    /// Decodes the image into separate red, green and blue planes, in this order.
+    ///
+    /// Each plane holds `width * height` samples in row-major order, with the same values as the
+    /// interleaved image of `read_image`. The alpha plane of images with alpha is not included.
+    pub fn read_image_planar(self) -> ImageResult<(Vec<u8>, Vec<u8>, Vec<u8>)> {
+        let mut image = vec![0; self.total_bytes() as usize];
+        self.fill_image(&mut image);
 
-    fn dimensions(&self) -> (u32, u32) {
-        (u32::from(self.frame.width), u32::from(self.frame.height))
+        let channels = usize::from(self.color_type().channel_count());
+        let size = image.len() / channels;
+        let mut red = Vec::with_capacity(size);
+        let mut green = Vec::with_capacity(size);
+        let mut blue = Vec::with_capacity(size);
+        for pixel in image.chunks(channels) {
+            red.push(pixel[0]);
+            green.push(pixel[1]);
+            blue.push(pixel[2]);
+        }
+
+        Ok((red, green, blue))
     }
 
-    fn color_type(&self) -> color::ColorType {
-        color::ColorType::L8
+    /// Returns the decoded planes of a still image as YUV 4:2:0, without any conversion.
+    ///
+    /// The planes are accessed with `Frame::y_plane`, `Frame::u_plane` and `Frame::v_plane`. The
+    /// luma plane has the dimensions of the image and the chroma planes half of them in both
+    /// directions, rounded up, with each chroma sample covering a 2x2 block of luma samples. The
+    /// alpha plane is not included. Animations are composited in RGB, so their frames cannot be
+    /// returned as YUV and are an unsupported error.
+    pub fn into_yuv420(self) -> ImageResult<Frame> {
+        if !self.frames.is_empty() {
+            return Err(ImageError::Unsupported(UnsupportedError::from_format_and_kind(
+                ImageFormat::WebP.into(),
+                UnsupportedErrorKind::GenericFeature("YUV output of animations".to_owned()),
+            )));
+        }
+
+        Ok(self.frame)
     }
 
-    fn into_reader(self) -> ImageResult<Self::Reader> {
-        Ok(WebpReader(Cursor::new(self.frame.ybuf), PhantomData))
+    /// Returns the luma, blue-difference and red-difference planes of a still image as YUV 4:4:4,
+    /// in this order.
+    ///
+    /// The chroma planes are upsampled to the dimensions of the luma plane with the filter set
+    /// by `set_upsampling`, so that each plane holds `width * height` samples in row-major order.
+    /// These are the samples converted to RGB by `read_image`. Like for `into_yuv420` the alpha
+    /// plane is not included and animations are an unsupported error.
+    pub fn into_yuv444(self) -> ImageResult<(Vec<u8>, Vec<u8>, Vec<u8>)> {
+        let filter = self.upsampling;
+        let frame = self.into_yuv420()?;
+
+        let (luma, width, height, _) = frame.y_plane();
+        let (width, height) = (usize::from(width), usize::from(height));
+        let upsample = |(plane, chroma_width, chroma_height, _): (&[u8], u16, u16, usize)| {
+            let (chroma_width, chroma_height) = (usize::from(chroma_width), usize::from(chroma_height));
+            let mut upsampled = vec![0; width * height];
+            if width > 0 {
+                for (y, row) in upsampled.chunks_mut(width).enumerate() {
+                    yuv::upsample_row(filter, plane, chroma_width, chroma_height, y, row);
+                }
+            }
+            upsampled
+        };
+
+        let (u, v) = (upsample(frame.u_plane()), upsample(frame.v_plane()));
+        Ok((luma.to_vec(), u, v))
     }
 
-    fn read_image(self, buf: &mut [u8]) -> ImageResult<()> {
-        assert_eq!(u64::try_from(buf.len()), Ok(self.total_bytes()));
-        buf.copy_from_slice(&self.frame.ybuf);
+    /// Splits the image into tiles of `tile_width` by `tile_height` pixels.
+    ///
+    /// The image is decoded once and yields the tiles row by row, each with its samples laid out
+    /// like the image returned by `read_image`. Tiles at the right and bottom edges are returned
+    /// at their true size if the dimensions are not a multiple of the tile size.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the tile width or height is zero.
+    pub fn into_tiles(
+        self,
+        tile_width: u32,
+        tile_height: u32,
+    ) -> impl Iterator<Item = (TileCoord, Vec<u8>)> {
+        assert!(tile_width > 0 && tile_height > 0, "tiles must not be empty");
+
+        let (width, height) = self.dimensions();
+        let bpp = usize::from(self.color_type().bytes_per_pixel());
+        let mut image = vec![0; self.total_bytes() as usize];
+        self.fill_image(&mut image);
+
+        let columns = width / tile_width + u32::from(width % tile_width != 0);
+        let rows = height / tile_height + u32::from(height % tile_height != 0);
+
+        (0..rows)
+            .flat_map(move |row| (0..columns).map(move |column| (column, row)))
+            .map(move |(column, row)| {
+                let x = column * tile_width;
+                let y = row * tile_height;
+                let coord = TileCoord {
+                    column,
+                    row,
+                    x,
+                    y,
+                    width: cmp::min(tile_width, width - x),
+                    height: cmp::min(tile_height, height - y),
+                };
+
+                let stride = width as usize * bpp;
+                let line = coord.width as usize * bpp;
+                let mut tile = Vec::with_capacity(line * coord.height as usize);
+                for y in y..y + coord.height {
+                    let start = y as usize * stride + x as usize * bpp;
+                    tile.extend_from_slice(&image[start..start + line]);
+                }
+
+                (coord, tile)
+            })
+    }
+
+    /// Passes the rows of the image to `callback` one at a time, without an output buffer for the
+    /// whole image.
+    ///
+    /// The callback receives the index of each row and its samples, laid out as in the buffer
+    /// of `read_image`. The rows are converted and delivered from top to bottom, each exactly
+    /// once, on the calling thread. The slice is only valid for the duration of the call.
+    pub fn decode_rows(self, mut callback: impl FnMut(usize, &[u8])) -> ImageResult<()> {
+        let (width, height) = self.dimensions();
+        let channels = usize::from(self.color_type().channel_count());
+        let mut row = vec![0; channels * width as usize];
+
+        // Scaling needs the whole image.
+        if self.scaled_size().is_some() {
+            let data = self.decode_to_vec()?;
+            for (y, row) in data.chunks(row.len()).enumerate() {
+                callback(y, row);
+            }
+            return Ok(());
+        }
+
+        let canvas = self.composite_first_frame();
+        for y in 0..height as usize {
+            self.fill_row(canvas.as_ref(), y, &mut row);
+            callback(y, &row);
+        }
+
+        Ok(())
+    }
+
+    /// Decodes the image into an `RgbaImage`.
+    ///
+    /// Images without alpha are returned as opaque. Animations return their first frame, as
+    /// composited on the canvas.
+    pub fn to_rgba_image(self) -> ImageResult<RgbaImage> {
+        self.decode_rgba()
+    }
+
+    fn decode_rgba(&self) -> ImageResult<RgbaImage> {
+        let (width, height) = self.dimensions();
+        let data = self.decode_to_vec()?;
+        let rgba = if self.color_type().has_alpha() {
+            data
+        } else {
+            let mut rgba = Vec::with_capacity(data.len() / 3 * 4);
+            for rgb in data.chunks(3) {
+                rgba.extend_from_slice(rgb);
+                rgba.push(255);
+            }
+            rgba
+        };
+
+        Ok(RgbaImage::from_raw(width, height, rgba).expect("The buffer has the size of the image"))
+    }
+
+    /// Decodes the image into an `RgbImage`.
+    ///
+    /// The alpha channel of images with alpha is discarded, leaving the color of transparent
+    /// pixels as stored, or black around the frames of an animation.
+    pub fn to_rgb_image(self) -> ImageResult<RgbImage> {
+        let (width, height) = self.dimensions();
+        let data = self.decode_to_vec()?;
+        let rgb = if self.color_type().has_alpha() {
+            let mut rgb = Vec::with_capacity(data.len() / 4 * 3);
+            for rgba in data.chunks(4) {
+                rgb.extend_from_slice(&rgba[..3]);
+            }
+            rgb
+        } else {
+            data
+        };
+
+        Ok(RgbImage::from_raw(width, height, rgb).expect("The buffer has the size of the image"))
+    }
+
+    /// Decodes the image into a `GrayImage`.
+    ///
+    /// The luma is computed from the RGB image of `to_rgb_image` as by `imageops::grayscale`,
+    /// so that it agrees with the conversions of the rest of the crate.
+    pub fn to_luma_image(self) -> ImageResult<GrayImage> {
+        Ok(imageops::grayscale(&self.to_rgb_image()?))
+    }
+
+    /// Decodes the image into a `GrayAlphaImage`, for masks and other grayscale content.
+    ///
+    /// The luma is computed as by `to_luma_image` and paired with the alpha plane at full
+    /// resolution. Images without alpha are returned as opaque.
+    pub fn to_luma_alpha_image(self) -> ImageResult<GrayAlphaImage> {
+        Ok(self.to_rgba_image()?.convert())
+    }
+
+    /// Decodes the image and resizes it to `width` by `height` pixels with `filter`.
+    ///
+    /// Large reductions run in two stages. VP8 predicts each block from the reconstructed pixels
+    /// around it, so unlike JPEG the image cannot be downscaled while its coefficients are
+    /// decoded. Instead the decoded image is first reduced by the largest power of two that
+    /// leaves at least twice the target size in both dimensions, averaging blocks of pixels, and
+    /// only that is resampled with `filter`. Below a ratio of four in either dimension only the
+    /// resampling runs, and it is skipped for the size of the image itself.
+    ///
+    /// Images with alpha and animations, of which the first frame is decoded, are returned as
+    /// RGBA and other images as RGB. The target size must not be zero.
+    pub fn decode_and_resize(
+        self,
+        width: u32,
+        height: u32,
+        filter: FilterType,
+    ) -> ImageResult<DynamicImage> {
+        if width == 0 || height == 0 {
+            return Err(ImageError::Parameter(ParameterError::from_kind(
+                ParameterErrorKind::DimensionMismatch,
+            )));
+        }
+
+        let (source_width, source_height) = self.dimensions();
+        let mut factor = 1;
+        while source_width / (2 * factor) >= 2 * width && source_height / (2 * factor) >= 2 * height {
+            factor *= 2;
+        }
+
+        Ok(match self.color_type() {
+            color::ColorType::Rgba8 => {
+                let image = reduce(&self.to_rgba_image()?, factor);
+                DynamicImage::ImageRgba8(resize_to(image, width, height, filter))
+            }
+            _ => {
+                let image = reduce(&self.to_rgb_image()?, factor);
+                DynamicImage::ImageRgb8(resize_to(image, width, height, filter))
+            }
+        })
+    }
+
+    /// Decodes the image into a new buffer, failing if its size does not fit into memory.
+    fn decode_to_vec(&self) -> ImageResult<Vec<u8>> {
+        let len = usize::try_from(self.total_bytes()).map_err(|_| {
+            ImageError::Limits(LimitError::from_kind(LimitErrorKind::InsufficientMemory))
+        })?;
+
+        let mut data = vec![0; len];
+        self.fill_image(&mut data);
+        Ok(data)
+    }
+
+    /// Decodes the image data and discards it, without converting it to pixels.
+    ///
+    /// This reconstructs every frame of an animation, but neither converts the planes to RGB
+    /// nor composites the frames on the canvas, to measure the cost of decoding on its own in
+    /// benchmarks. Unlike `validate` it detects corrupted image data. Still images and the first
+    /// frame of animations are decoded when the decoder is created, so this has no work left for
+    /// them and decoding is measured together with `new`.
+    pub fn decode_discard(self) -> ImageResult<()> {
+        let extended = self.extended.unwrap_or_default();
+        for data in self.frames.iter().skip(1) {
+            let chunks = FrameChunks::parse(data)?;
+            let rect = chunks.header.rect;
+            chunks
+                .decode()
+                .map_err(|err| FrameError::wrap(rect.width as u16, rect.height as u16, err))?;
+            check_bounds(rect, extended.canvas_width, extended.canvas_height)?;
+        }
+
         Ok(())
     }
+
+    /// Decodes all frames and lays them out in a single RGBA image, as a sprite atlas.
+    ///
+    /// Each frame is composited on the canvas as by `into_frames`, so that all of them have the
+    /// size of the canvas. The frames are packed in rows of a grid with as many columns as the
+    /// square root of the number of frames rounded up, from left to right and top to bottom. The
+    /// last row may be incomplete, leaving a transparent area. For each frame its rectangle in
+    /// the atlas and its delay are returned, in the order of the animation.
+    ///
+    /// A still image is returned as an atlas of a single frame without delay.
+    pub fn into_atlas(self) -> ImageResult<(RgbaImage, Vec<(Rect, Delay)>)> {
+        let frames = self.into_frames().collect_frames()?;
+        let (width, height) = match frames.first() {
+            Some(frame) => frame.buffer().dimensions(),
+            None => return Ok((RgbaImage::new(0, 0), Vec::new())),
+        };
+
+        let mut columns = 1;
+        while columns * columns < frames.len() {
+            columns += 1;
+        }
+        let rows = (frames.len() + columns - 1) / columns;
+
+        let too_large = || ImageError::Limits(LimitError::from_kind(LimitErrorKind::DimensionError));
+        let atlas_width = u32::try_from(columns).ok().and_then(|c| c.checked_mul(width));
+        let atlas_height = u32::try_from(rows).ok().and_then(|r| r.checked_mul(height));
+        let mut atlas = match (atlas_width, atlas_height) {
+            (Some(atlas_width), Some(atlas_height)) => RgbaImage::new(atlas_width, atlas_height),
+            _ => return Err(too_large()),
+        };
+
+        let mut rects = Vec::with_capacity(frames.len());
+        for (i, frame) in frames.iter().enumerate() {
+            let rect = Rect {
+                x: (i % columns) as u32 * width,
+                y: (i / columns) as u32 * height,
+                width,
+                height,
+            };
+            atlas.copy_from(frame.buffer(), rect.x, rect.y)?;
+            rects.push((rect, frame.delay()));
+        }
+
+        Ok((atlas, rects))
+    }
+
+    /// Decodes all frames into memory, each with the duration it is shown for.
+    ///
+    /// The frames are composited on the canvas as by `into_frames`, so each image is the whole
+    /// canvas after drawing the frame. A still image is a single frame of zero duration. Animations
+    /// with more frames than `Limits::max_frames` are a limit error before any of them is decoded.
+    pub fn collect_frames(self) -> ImageResult<Vec<(Duration, RgbaImage)>> {
+        if self.frames.len() > self.limits.max_frames as usize {
+            return Err(ImageError::Limits(LimitError::from_kind(
+                LimitErrorKind::InsufficientMemory,
+            )));
+        }
+
+        let mut frames = Vec::with_capacity(cmp::max(self.frames.len(), 1));
+        for frame in self.into_frames() {
+            let frame = frame?;
+            let (numer, denom) = frame.delay().numer_denom_ms();
+            let duration = Duration::from_millis(u64::from(numer) / u64::from(denom));
+            frames.push((duration, frame.into_buffer()));
+        }
+
+        Ok(frames)
+    }
+
+    /// Returns the frames as by `into_frames`, but from the last to the first.
+    ///
+    /// As frames are composited on the result of the previous ones, each frame is drawn again
+    /// from the last key frame before it, one whose canvas does not depend on the earlier frames.
+    /// Only the key frames themselves are decoded once, which makes reversing animations of full
+    /// canvas frames cheap. Otherwise the cost grows with the distance to the key frame, up to
+    /// decoding all earlier frames for each one, quadratic in the number of frames overall.
+    pub fn into_frames_reversed(self) -> Frames<'static> {
+        if self.frames.is_empty() {
+            let frame = self.to_rgba_image().map(animation::Frame::new);
+            return Frames::new(Box::new(std::iter::once(frame)));
+        }
+
+        let extended = self.extended.unwrap_or_default();
+        let (width, height) = (extended.canvas_width, extended.canvas_height);
+        let background = self.background_color();
+        let (premultiply, rounding, upsampling) = (self.premultiply, self.rounding, self.upsampling);
+        let min_duration = self.min_frame_duration;
+        let data = self.frames;
+
+        let frames = (0..data.len()).rev().map(move |target| {
+            let canvas = Canvas::new(width, height, background);
+            let (mut image, duration) = draw_from_key_frame(&data, target, canvas, upsampling)?;
+            if premultiply {
+                premultiply_image(&mut image, rounding);
+            }
+
+            let delay = Delay::from_numer_denom_ms(cmp::max(duration, min_duration), 1);
+            Ok(animation::Frame::from_parts(image, 0, 0, delay))
+        });
+
+        Frames::new(Box::new(frames))
+    }
+
+    /// Decodes a single representative image, the still image of the file if it has one.
+    ///
+    /// Animations do not contain a still image according to the specification, but some files
+    /// hold a `VP8 ` chunk next to the `ANMF` chunks for viewers without animation support. That
+    /// chunk is ignored otherwise, and an error in strict mode, and it is decoded on its own
+    /// without alpha if present. Otherwise this is the first frame as composited on the canvas,
+    /// as by `to_rgba_image`, and the image itself for still images.
+    pub fn decode_still(self) -> ImageResult<RgbaImage> {
+        let data = match self.default_image {
+            Some(ref data) => data,
+            None => return self.decode_rgba(),
+        };
+
+        let (width, height) = keyframe_dimensions(data)?;
+        let header = FrameHeader {
+            rect: Rect { x: 0, y: 0, width: u32::from(width), height: u32::from(height) },
+            duration: 0,
+            blend: false,
+            dispose: false,
+        };
+        let frame = FrameChunks { header, alpha: None, vp8: data }
+            .decode()
+            .map_err(|err| FrameError::wrap(width, height, err))?;
+
+        let mut canvas = Canvas::new(header.rect.width, header.rect.height, [0; 4]);
+        canvas.draw(&frame, self.upsampling)?;
+        Ok(canvas.into_image())
+    }
+
+    /// Returns the canvas of an animation at `ms` milliseconds after it started, as it would be
+    /// shown by `into_frames`.
+    ///
+    /// The frame shown at that time is drawn from the last key frame before it, as described
+    /// for `into_frames_reversed`, so the earlier frames are only decoded if they are needed.
+    /// Frames are shown for their duration, with times beyond the end of the animation wrapping
+    /// around while it loops. After the number of loops of the `ANIM` chunk, if it is not zero
+    /// for infinite looping, the last frame stays visible. Animations whose frames all have no
+    /// duration show their last frame. A still image is returned at any time.
+    pub fn frame_at_time(&self, ms: u64) -> ImageResult<RgbaImage> {
+        if self.frames.is_empty() {
+            return self.decode_rgba();
+        }
+
+        let mut durations = Vec::with_capacity(self.frames.len());
+        for data in &self.frames {
+            let duration = FrameHeader::parse(data)?.duration;
+            durations.push(u64::from(cmp::max(duration, self.min_frame_duration)));
+        }
+
+        let total: u64 = durations.iter().sum();
+        let loops = u64::from(self.loop_count.unwrap_or(0));
+        let target = if total == 0 || (loops != 0 && ms / total >= loops) {
+            self.frames.len() - 1
+        } else {
+            let mut time = ms % total;
+            let mut target = 0;
+            while time >= durations[target] {
+                time -= durations[target];
+                target += 1;
+            }
+            target
+        };
+
+        let (width, height) = self.dimensions();
+        let canvas = Canvas::new(width, height, self.background_color());
+        let (mut image, _) = draw_from_key_frame(&self.frames, target, canvas, self.upsampling)?;
+        if self.premultiply {
+            premultiply_image(&mut image, self.rounding);
+        }
+
+        Ok(image)
+    }
+
+    /// Converts the decoded image to the samples of its color type.
+    fn fill_image(&self, buf: &mut [u8]) {
+        let channels = usize::from(self.color_type().channel_count());
+        if let Some((width, height)) = self.scaled_size() {
+            let image_width = u32::from(self.frame.width);
+            let image_height = u32::from(self.frame.height);
+            let mut image = vec![0; channels * image_width as usize * image_height as usize];
+            for (y, row) in image.chunks_mut(channels * image_width as usize).enumerate() {
+                self.fill_row(None, y, row);
+            }
+
+            let filter = FilterType::Triangle;
+            let scaled = match self.color_type() {
+                color::ColorType::Rgba8 => {
+                    let image = RgbaImage::from_raw(image_width, image_height, image).unwrap();
+                    resize_to(image, width, height, filter).into_raw()
+                }
+                _ => {
+                    let image = RgbImage::from_raw(image_width, image_height, image).unwrap();
+                    resize_to(image, width, height, filter).into_raw()
+                }
+            };
+            buf.copy_from_slice(&scaled);
+            return;
+        }
+
+        let stride = channels * self.dimensions().0 as usize;
+        let canvas = self.composite_first_frame();
+        for (y, row) in buf.chunks_mut(stride).enumerate() {
+            self.fill_row(canvas.as_ref(), y, row);
+        }
+    }
+
+    /// Draws the first frame of an animation on the canvas, or returns `None` for still images.
+    fn composite_first_frame(&self) -> Option<RgbaImage> {
+        self.first_frame.as_ref().map(|frame| {
+            let (width, height) = self.dimensions();
+            let mut canvas = Canvas::new(width, height, self.background_color());
+            canvas
+                .draw(frame, self.upsampling)
+                .expect("The bounds of the first frame are checked when the decoder is created");
+            canvas.into_image()
+        })
+    }
+
+    /// Writes row `y` of the image, with the samples of `read_image`, into `row`.
+    ///
+    /// `canvas` is the first frame composited on the canvas, for animations.
+    fn fill_row(&self, canvas: Option<&RgbaImage>, y: usize, row: &mut [u8]) {
+        if let Some(canvas) = canvas {
+            row.copy_from_slice(&(**canvas)[y * row.len()..][..row.len()]);
+        } else if let Some(ref alpha) = self.alpha {
+            // Convert to RGB in place, then spread the pixels out from the end of the row.
+            let width = row.len() / 4;
+            self.frame.fill_rgb_row(y, &mut row[..3 * width], self.upsampling);
+            for x in (0..width).rev() {
+                let (r, g, b) = (row[3 * x], row[3 * x + 1], row[3 * x + 2]);
+                row[4 * x..4 * x + 4].copy_from_slice(&[r, g, b, alpha[y * width + x]]);
+            }
+        } else {
+            self.frame.fill_rgb_row(y, row, self.upsampling);
+            return;
+        }
+
+        if self.premultiply {
+            for pixel in row.chunks_mut(4) {
+                let alpha = pixel[3];
+                for channel in &mut pixel[..3] {
+                    *channel = self.rounding.premultiply(*channel, alpha);
+                }
+            }
+        }
+    }
+
+    fn read_riff_header(&mut self) -> ImageResult<u32> {
+        let riff = read_field(&mut self.r, "RIFF signature")?;
+        if &riff != b"RIFF" {
+            return Err(ImageError::Decoding(DecodingError::with_message(
+                ImageFormat::WebP.into(),
+                "Invalid RIFF signature".to_string(),
+            )));
+        }
+
+        let size = read_length(&mut self.r, "RIFF size")?;
+        let webp = read_field(&mut self.r, "WEBP signature")?;
+        if &webp != b"WEBP" {
+            return Err(ImageError::Decoding(DecodingError::with_message(
+                ImageFormat::WebP.into(),
+                "Invalid WEBP signature".to_string(),
+            )));
+        }
+
+        // The size includes the `WEBP` signature.
+        self.riff_size = u64::from(size).saturating_sub(4);
+        self.riff_remaining = self.riff_size;
+
+        Ok(size)
+    }
+
+    fn read_vp8_header(&mut self) -> ImageResult<ImageChunk> {
+        loop {
+            // Every chunk consumes at least its header, so this also ends the loop for a reader
+            // that never runs out of empty chunks.
+            if self.riff_remaining < 8 {
+                if !self.frames.is_empty() {
+                    return Ok(ImageChunk::Frames);
+                }
+
+                return Err(ImageError::Decoding(DecodingError::with_message(
+                    ImageFormat::WebP.into(),
+                    "No image data in the RIFF payload".to_string(),
+                )));
+            }
+
+            self.count_chunk()?;
+            let chunk = read_field(&mut self.r, "chunk fourcc")?;
+
+            // Once the first frame of an animation is found, only further frames are read.
+            let image_chunk = &chunk == b"VP8 " || &chunk == b"VP8L" || &chunk == b"ALPH";
+            if !self.frames.is_empty() && image_chunk {
+                if self.strict {
+                    return Err(ImageError::Decoding(DecodingError::with_message(
+                        ImageFormat::WebP.into(),
+                        "Image contains both animation frames and a still image".to_string(),
+                    )));
+                }
+
+                self.warn(DecodeWarning::IgnoredImageChunk)?;
+                if &chunk == b"VP8 " && self.default_image.is_none() {
+                    // Kept for `decode_still`, as viewers without animation support may show it.
+                    self.default_image = Some(self.read_padded_chunk()?);
+                } else {
+                    self.skip_chunk()?;
+                }
+                continue;
+            }
+
+            match &chunk {
+                b"VP8 " => {
+                    if self.has_animation_flag() {
+                        if self.strict {
+                            return Err(ImageError::Decoding(DecodingError::with_message(
+                                ImageFormat::WebP.into(),
+                                "Animation flag is set but the image contains no animation frames"
+                                    .to_string(),
+                            )));
+                        }
+                        self.warn(DecodeWarning::AnimationFlagWithoutFrames)?;
+                    }
+
+                    let len = read_length(&mut self.r, "chunk length")?;
+                    self.consume(8);
+                    return Ok(ImageChunk::Vp8(len));
+                }
+                b"VP8X" => {
+                    let len = read_length(&mut self.r, "chunk length")?;
+                    self.read_extended_header(len)?;
+                    self.consume(8 + u64::from(len) + u64::from(len % 2));
+                }
+                b"ANMF" => {
+                    if !self.has_animation_flag() && self.frames.is_empty() {
+                        if self.strict {
+                            return Err(ImageError::Decoding(DecodingError::with_message(
+                                ImageFormat::WebP.into(),
+                                "Image contains animation frames but the animation flag is not set"
+                                    .to_string(),
+                            )));
+                        }
+                        self.warn(DecodeWarning::FramesWithoutAnimationFlag)?;
+                    }
+
+                    if self.extended.is_none() {
+                        return Err(ImageError::Decoding(DecodingError::with_message(
+                            ImageFormat::WebP.into(),
+                            "Animation frames without a VP8X chunk".to_string(),
+                        )));
+                    }
+
+                    let data = self.read_padded_chunk()?;
+                    self.frames.push(data);
+                }
+                b"ALPH" => self.alpha_chunk = Some(self.read_padded_chunk()?),
+                b"ICCP" => {
+                    let profile = self.read_padded_chunk()?;
+                    if !profile.is_empty() {
+                        self.icc_profile = Some(profile);
+                    } else if self.strict {
+                        return Err(ImageError::Decoding(DecodingError::with_message(
+                            ImageFormat::WebP.into(),
+                            "ICCP chunk is empty".to_string(),
+                        )));
+                    } else {
+                        self.warn(DecodeWarning::EmptyIccProfile)?;
+                    }
+                }
+                b"ANIM" => {
+                    // The background color in BGRA order, followed by the loop count.
+                    let data = self.read_padded_chunk()?;
+                    if data.len() < 6 {
+                        return Err(ImageError::Decoding(DecodingError::with_message(
+                            ImageFormat::WebP.into(),
+                            format!("ANIM chunk too short: {} bytes", data.len()),
+                        )));
+                    }
+                    self.background = Some([data[2], data[1], data[0], data[3]]);
+                    self.loop_count = Some(LittleEndian::read_u16(&data[4..6]));
+                }
+                b"VP8L" => {
+                    if self.strict {
+                        let len = read_length(&mut self.r, "chunk length")?;
+                        self.consume(8);
+                        self.skip_contents(len)?;
+                        self.skip_trailing_chunks(false)?;
+                    }
+
+                    return Err(ImageError::Unsupported(
+                        UnsupportedError::from_format_and_kind(
+                            ImageFormat::WebP.into(),
+                            UnsupportedErrorKind::GenericFeature(
+                                "Lossless WebP images are not supported".to_owned(),
+                            ),
+                        ),
+                    ));
+                }
+                _ => self.skip_chunk()?,
+            }
+        }
+    }
+
+    /// Reads the length and contents of a chunk whose fourcc has been read, and its padding.
+    fn read_padded_chunk(&mut self) -> ImageResult<Vec<u8>> {
+        let len = read_length(&mut self.r, "chunk length")?;
+        let data = self.read_chunk(len)?;
+        if len % 2 != 0 {
+            self.r.read_u8()?;
+        }
+        self.consume(8 + u64::from(len) + u64::from(len % 2));
+        Ok(data)
+    }
+
+    /// Skips the length and contents of a chunk whose fourcc has been read.
+    fn skip_chunk(&mut self) -> ImageResult<()> {
+        let len = read_length(&mut self.r, "chunk length")?;
+        self.consume(8);
+        self.skip_contents(len)?;
+        Ok(())
+    }
+
+    /// Skips the contents of a chunk of length `len` and its padding, without reading past the
+    /// end of the RIFF payload. Returns whether the reader held all of it.
+    ///
+    /// A chunk longer than the rest of the payload is an error in strict mode, otherwise it is
+    /// cut at the end of the payload, so that a file following in the same stream is not read.
+    fn skip_contents(&mut self, len: u32) -> ImageResult<bool> {
+        if u64::from(len) > self.riff_remaining {
+            if self.strict {
+                return Err(ImageError::Decoding(DecodingError::with_message(
+                    ImageFormat::WebP.into(),
+                    format!(
+                        "Chunk of {} bytes exceeds the {} bytes left in the RIFF payload",
+                        len, self.riff_remaining
+                    ),
+                )));
+            }
+            self.warn(DecodeWarning::Truncated)?;
+        }
+
+        // RIFF chunks containing an uneven number of bytes append an extra 0x00 at the end.
+        let padded = u64::from(len) + u64::from(len % 2);
+        let len = cmp::min(padded, self.riff_remaining);
+        let skipped = io::copy(&mut self.r.by_ref().take(len), &mut io::sink())?;
+        self.consume(skipped);
+        Ok(skipped == len)
+    }
+
+    /// Reads the next chunk for `dump_structure`, with the contents of the chunks it describes.
+    fn read_chunk_for_dump(&mut self) -> ImageResult<([u8; 4], u32, Option<Vec<u8>>)> {
+        let fourcc = read_field(&mut self.r, "chunk fourcc")?;
+
+        if &fourcc == b"VP8X" || &fourcc == b"ANMF" {
+            let data = self.read_padded_chunk()?;
+            return Ok((fourcc, data.len() as u32, Some(data)));
+        }
+
+        let len = read_length(&mut self.r, "chunk length")?;
+        let padded = u64::from(len) + u64::from(len % 2);
+        let skipped = io::copy(&mut self.r.by_ref().take(padded), &mut io::sink())?;
+        self.consume(8 + skipped);
+        if skipped < u64::from(len) {
+            return Err(truncated(u64::from(len), skipped));
+        }
+
+        Ok((fourcc, len, None))
+    }
+
+    /// Skips the rest of the RIFF payload after the image data.
+    ///
+    /// The remaining chunks are walked to find a second image chunk, which is an error in strict
+    /// mode. A file cut short in its trailing chunks still has a complete image. With
+    /// `late_alpha` an `ALPH` chunk is decoded for the still image if it had none before.
+    fn skip_trailing_chunks(&mut self, late_alpha: bool) -> ImageResult<()> {
+        while self.riff_remaining >= 8 {
+            self.count_chunk()?;
+            let mut header = Vec::with_capacity(8);
+            self.r.by_ref().take(8).read_to_end(&mut header)?;
+            self.consume(header.len() as u64);
+            if header.len() < 8 {
+                if self.strict {
+                    return Err(truncated(8, header.len() as u64));
+                }
+                break;
+            }
+
+            if &header[..4] == b"VP8 " || &header[..4] == b"VP8L" {
+                if self.strict {
+                    return Err(ImageError::Decoding(DecodingError::with_message(
+                        ImageFormat::WebP.into(),
+                        "Image contains more than one VP8 or VP8L chunk".to_string(),
+                    )));
+                }
+                self.warn(DecodeWarning::IgnoredImageChunk)?;
+            }
+
+            let len = LittleEndian::read_u32(&header[4..]);
+            if &header[..4] == b"ALPH" && late_alpha && self.alpha.is_none() {
+                self.warn(DecodeWarning::AlphaAfterImage)?;
+                let data = self.read_chunk(len)?;
+                if len % 2 != 0 {
+                    self.r.read_u8()?;
+                }
+                self.consume(u64::from(len) + u64::from(len % 2));
+
+                let (width, height) = (self.frame.width, self.frame.height);
+                self.attach_alpha(&data).map_err(|err| FrameError::wrap(width, height, err))?;
+                continue;
+            }
+
+            if !self.skip_contents(len)? {
+                break;
+            }
+        }
+
+        // Less than a chunk header may be left, such as the padding of the image data.
+        let remaining = self.riff_remaining;
+        let skipped = io::copy(&mut self.r.by_ref().take(remaining), &mut io::sink())?;
+        self.consume(skipped);
+        if self.riff_remaining > 0 {
+            self.warn(DecodeWarning::Truncated)?;
+        }
+
+        Ok(())
+    }
+
+    /// Records a deviation from the specification, or fails with it if warnings are errors.
+    fn warn(&mut self, warning: DecodeWarning) -> ImageResult<()> {
+        if self.limits.treat_warnings_as_errors {
+            return Err(ImageError::Decoding(DecodingError::new(ImageFormat::WebP.into(), warning)));
+        }
+
+        self.warnings.push(warning);
+        Ok(())
+    }
+
+    /// Records that another chunk is read, failing if there are more than allowed.
+    fn count_chunk(&mut self) -> ImageResult<()> {
+        self.chunks += 1;
+        if self.chunks > self.limits.max_chunks {
+            return Err(ImageError::Decoding(DecodingError::with_message(
+                ImageFormat::WebP.into(),
+                format!("More than {} chunks in the RIFF payload", self.limits.max_chunks),
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Records that `n` bytes of the RIFF payload have been read.
+    fn consume(&mut self, n: u64) {
+        self.riff_remaining = self.riff_remaining.saturating_sub(n);
+    }
+
+    fn has_animation_flag(&self) -> bool {
+        match self.extended {
+            Some(ExtendedHeader { animation, .. }) => animation,
+            None => false,
+        }
+    }
+
+    fn read_extended_header(&mut self, len: u32) -> ImageResult<()> {
+        if len < 10 {
+            return Err(ImageError::Decoding(DecodingError::with_message(
+                ImageFormat::WebP.into(),
+                format!("VP8X chunk too short: {} bytes", len),
+            )));
+        }
+
+        let flags = self.r.read_u8()?;
+        let reserved = self.r.read_u24::<LittleEndian>()?;
+        let canvas_width = self.r.read_u24::<LittleEndian>()? + 1;
+        let canvas_height = self.r.read_u24::<LittleEndian>()? + 1;
+
+        // The dimensions are stored minus one, so they are at least one but may be up to 2^24.
+        if canvas_width > MAX_CANVAS_DIMENSION || canvas_height > MAX_CANVAS_DIMENSION {
+            return Err(ImageError::Decoding(DecodingError::with_message(
+                ImageFormat::WebP.into(),
+                format!("Invalid VP8X canvas size {}x{}", canvas_width, canvas_height),
+            )));
+        }
+
+        // Any unknown trailing data and the padding byte of uneven chunks
+        let remaining = u64::from(len - 10) + u64::from(len % 2);
+        io::copy(&mut self.r.by_ref().take(remaining), &mut io::sink())?;
+
+        self.extended = Some(ExtendedHeader {
+            animation: flags & 0b0000_0010 != 0,
+            canvas_width,
+            canvas_height,
+        });
+
+        if flags & 0b1100_0001 != 0 || reserved != 0 {
+            self.warn(DecodeWarning::ReservedBits)?;
+        }
+
+        Ok(())
+    }
+
+    fn read_frame(&mut self, len: u32) -> ImageResult<()> {
+        // Parse the dimensions first, so that they can be reported if reconstruction fails.
+        let mut header = [0u8; 10];
+        if len < header.len() as u32 {
+            return Err(ImageError::Decoding(DecodingError::with_message(
+                ImageFormat::WebP.into(),
+                format!("VP8 chunk too short: {} bytes", len),
+            )));
+        }
+        self.r.read_exact(&mut header)?;
+        let (width, height) = keyframe_dimensions(&header)?;
+        check_first_partition(&header, len).map_err(|err| FrameError::wrap(width, height, err))?;
+
+        self.check_canvas_size(width, height)?;
+
+        // The image data is decoded straight from the reader, so that it is only copied once
+        // into the partitions of the VP8 decoder.
+        let mut data = self.r.by_ref().take(u64::from(len) - header.len() as u64);
+        let mut vp8 = Vp8Decoder::new(Cursor::new(header).chain(&mut data));
+        vp8.set_data_len(len);
+        vp8.set_partial(!self.strict);
+        let result = vp8.decode_frame_into(&mut self.frame);
+        let (intact_rows, partial) = (vp8.intact_rows(), vp8.is_partial());
+        drop(vp8);
+
+        io::copy(&mut data, &mut io::sink())?;
+        let read = u64::from(len) - data.limit();
+        // Outside of strict mode the rows decoded before the data ran out are kept.
+        if read < u64::from(len) && (self.strict || result.is_err() || intact_rows == 0) {
+            return Err(truncated(u64::from(len), read));
+        }
+
+        // The padding byte of uneven chunks, so that the chunks after it are read in step.
+        let padding = io::copy(&mut self.r.by_ref().take(u64::from(len % 2)), &mut io::sink())?;
+        self.consume(read + padding);
+
+        self.reconstruct(result)
+            .map_err(|err| FrameError::wrap(width, height, err))?;
+
+        if partial {
+            self.warn(DecodeWarning::PartialImage { rows: u32::from(intact_rows) })?;
+        }
+
+        Ok(())
+    }
+
+    /// Checks that a still image has the size of the `VP8X` canvas, which is an error in strict
+    /// mode.
+    fn check_canvas_size(&mut self, width: u16, height: u16) -> ImageResult<()> {
+        if let Some(extended) = self.extended {
+            let canvas = (extended.canvas_width, extended.canvas_height);
+            let image = (u32::from(width), u32::from(height));
+            if canvas != image {
+                let warning = DecodeWarning::CanvasSizeMismatch { canvas, image };
+                if self.strict {
+                    return Err(ImageError::Decoding(DecodingError::with_message(
+                        ImageFormat::WebP.into(),
+                        warning.to_string(),
+                    )));
+                }
+                self.warn(warning)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn reconstruct(&mut self, decoded: ImageResult<()>) -> ImageResult<()> {
+        decoded?;
+        self.frame.check_planes()?;
+
+        match self.alpha_chunk.take() {
+            Some(data) => self.attach_alpha(&data),
+            None => Ok(()),
+        }
+    }
+
+    /// Decodes the contents of an `ALPH` chunk as the alpha plane of the still image.
+    fn attach_alpha(&mut self, data: &[u8]) -> ImageResult<()> {
+        let alpha = alpha::decode_alpha(data, self.frame.width, self.frame.height)?;
+        debug_assert_eq!(
+            alpha.len(),
+            usize::from(self.frame.width) * usize::from(self.frame.height),
+            "Alpha plane inconsistent with the frame",
+        );
+        self.alpha = Some(alpha);
+        Ok(())
+    }
+
+    /// Reads the contents of a chunk of length `len`, failing if the file ends before.
+    fn read_chunk(&mut self, len: u32) -> ImageResult<Vec<u8>> {
+        let mut data = Vec::with_capacity(cmp::min(len, MAX_CHUNK_PREALLOCATION) as usize);
+        self.r.by_ref().take(u64::from(len)).read_to_end(&mut data)?;
+
+        if data.len() as u64 != u64::from(len) {
+            return Err(truncated(u64::from(len), data.len() as u64));
+        }
+
+        Ok(data)
+    }
+
+    fn read_metadata(&mut self) -> ImageResult<()> {
+        if !self.have_frame {
+            self.read_riff_header()?;
+            match self.read_vp8_header()? {
+                ImageChunk::Vp8(len) => self.read_frame(len)?,
+                ImageChunk::Frames => {
+                    let first = FrameChunks::parse(&self.frames[0])?;
+                    let rect = first.header.rect;
+                    let frame = first.decode().map_err(|err| {
+                        FrameError::wrap(rect.width as u16, rect.height as u16, err)
+                    })?;
+
+                    let extended = self.extended.unwrap_or_default();
+                    check_bounds(rect, extended.canvas_width, extended.canvas_height)?;
+                    self.first_frame = Some(frame);
+                }
+            }
+
+            // Skip the padding and any chunks after the image data, so that the reader ends up
+            // right after the RIFF payload. Another file may follow in the same stream.
+            let still = self.frames.is_empty();
+            self.skip_trailing_chunks(still)?;
+
+            self.have_frame = true;
+        }
+
+        Ok(())
+    }
+}
+
+/// Parses the frame tag and dimensions at the start of a VP8 keyframe, Section 9.1
+fn keyframe_dimensions(header: &[u8]) -> ImageResult<(u16, u16)> {
+    if header.len() < 10 || header[0] & 1 != 0 || header[3..6] != [0x9d, 0x01, 0x2a] {
+        return Err(ImageError::Decoding(DecodingError::with_message(
+            ImageFormat::WebP.into(),
+            "Invalid VP8 keyframe header".to_string(),
+        )));
+    }
+
+    let width = u16::from_le_bytes([header[6], header[7]]) & 0x3FFF;
+    let height = u16::from_le_bytes([header[8], header[9]]) & 0x3FFF;
+    Ok((width, height))
+}
+
+/// Checks that the first partition announced by the frame tag of a keyframe fits into the
+/// `len` bytes of its `VP8 ` chunk, after the 10 bytes of the keyframe header.
+pub(crate) fn check_first_partition(header: &[u8], len: u32) -> ImageResult<()> {
+    let tag = u32::from(header[0]) | u32::from(header[1]) << 8 | u32::from(header[2]) << 16;
+    let first_partition_size = tag >> 5;
+    if u64::from(first_partition_size) + 10 > u64::from(len) {
+        return Err(ImageError::Decoding(DecodingError::with_message(
+            ImageFormat::WebP.into(),
+            format!(
+                "First partition of {} bytes exceeds the VP8 chunk of {} bytes",
+                first_partition_size, len
+            ),
+        )));
+    }
+
+    Ok(())
+}
+
+/// Formats a fourcc, escaping bytes that are not printable ASCII.
+fn display_fourcc(fourcc: &[u8]) -> String {
+    fourcc.iter().flat_map(|&byte| ascii::escape_default(byte)).map(char::from).collect()
+}
+
+/// Describes the flags and canvas size of a `VP8X` chunk, for `dump_structure`.
+fn describe_extended_header(data: &[u8]) -> String {
+    if data.len() < 10 {
+        return "\n".to_string();
+    }
+
+    let names = [
+        (0b0010_0000, "icc"),
+        (0b0001_0000, "alpha"),
+        (0b0000_1000, "exif"),
+        (0b0000_0100, "xmp"),
+        (0b0000_0010, "animation"),
+    ];
+    let flags: Vec<_> = names
+        .iter()
+        .filter(|&&(flag, _)| data[0] & flag != 0)
+        .map(|&(_, name)| name)
+        .collect();
+
+    let width = LittleEndian::read_u24(&data[4..7]) + 1;
+    let height = LittleEndian::read_u24(&data[7..10]) + 1;
+    format!(": flags [{}], canvas {}x{}\n", flags.join(", "), width, height)
+}
+
+/// Describes an `ANMF` chunk whose contents start at `offset`, and lists the chunks within it.
+fn describe_frame(offset: u64, data: &[u8]) -> String {
+    let header = match FrameHeader::parse(data) {
+        Ok(header) => header,
+        Err(err) => return format!("\n  error at {}: {}\n", offset, err),
+    };
+
+    let rect = header.rect;
+    let mut description = format!(
+        ": frame {}x{} at ({}, {}), {} ms{}{}\n",
+        rect.width,
+        rect.height,
+        rect.x,
+        rect.y,
+        header.duration,
+        if header.blend { ", blend" } else { "" },
+        if header.dispose { ", dispose" } else { "" },
+    );
+
+    let mut position = ANMF_HEADER_SIZE;
+    while data.len() - position >= 8 {
+        let chunk = &data[position..];
+        let len = LittleEndian::read_u32(&chunk[4..8]);
+        let chunk_offset = offset + position as u64;
+        let fourcc = display_fourcc(&chunk[..4]);
+        description += &format!("  {} at {}, {} bytes\n", fourcc, chunk_offset, len);
+
+        let available = chunk.len() - 8;
+        if u64::from(len) > available as u64 {
+            let err = truncated(u64::from(len), available as u64);
+            description += &format!("  error at {}: {}\n", chunk_offset, err);
+            break;
+        }
+        position += 8 + len as usize + len as usize % 2;
+        position = cmp::min(position, data.len());
+    }
+
+    description
+}
+
+/// Reads a four byte field of the RIFF header or a chunk header, named `field` in the error if
+/// the file ends within it.
+fn read_field<R: Read>(r: &mut R, field: &str) -> ImageResult<[u8; 4]> {
+    let mut data = Vec::with_capacity(4);
+    r.take(4).read_to_end(&mut data)?;
+    if data.len() < 4 {
+        return Err(ImageError::Decoding(DecodingError::with_message(
+            ImageFormat::WebP.into(),
+            format!("Truncated WebP header: {} ends after {} of 4 bytes", field, data.len()),
+        )));
+    }
+
+    Ok([data[0], data[1], data[2], data[3]])
+}
+
+/// Reads a little endian length field as by `read_field`.
+fn read_length<R: Read>(r: &mut R, field: &str) -> ImageResult<u32> {
+    read_field(r, field).map(|data| LittleEndian::read_u32(&data))
+}
+
+/// The error for a chunk of which only `got` of the `expected` bytes are present.
+pub(crate) fn truncated(expected: u64, got: u64) -> ImageError {
+    ImageError::Decoding(DecodingError::with_message(
+        ImageFormat::WebP.into(),
+        format!("Truncated WebP: expected {} bytes, got {}", expected, got),
+    ))
+}
+
+/// The error for an output buffer with room for fewer samples than the decoded image.
+fn buffer_too_small() -> ImageError {
+    ImageError::Parameter(ParameterError::from_kind(ParameterErrorKind::DimensionMismatch))
+}
+
+/// Wrapper struct around a `Cursor<Vec<u8>>`
+pub struct WebpReader<R>(Cursor<Vec<u8>>, PhantomData<R>);
+impl<R> Read for WebpReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+    fn read_to_end(&mut self, buf: &mut Vec<u8>) -> io::Result<usize> {
+        if self.0.position() == 0 && buf.is_empty() {
+            mem::swap(buf, self.0.get_mut());
+            Ok(buf.len())
+        } else {
+            self.0.read_to_end(buf)
+        }
+    }
+}
+
+impl<'a, R: 'a + Read> ImageDecoder<'a> for WebPDecoder<R> {
+    type Reader = WebpReader<R>;
+
+    fn dimensions(&self) -> (u32, u32) {
+        if let Some(size) = self.scaled_size() {
+            return size;
+        }
+
+        match (&self.first_frame, self.extended) {
+            (Some(_), Some(extended)) => (extended.canvas_width, extended.canvas_height),
+            _ => (u32::from(self.frame.width), u32::from(self.frame.height)),
+        }
+    }
+
+    fn color_type(&self) -> color::ColorType {
+        if self.first_frame.is_some() || self.alpha.is_some() {
+            color::ColorType::Rgba8
+        } else {
+            color::ColorType::Rgb8
+        }
+    }
+
+    fn into_reader(self) -> ImageResult<Self::Reader> {
+        let mut data = vec![0; self.total_bytes() as usize];
+        self.fill_image(&mut data);
+        Ok(WebpReader(Cursor::new(data), PhantomData))
+    }
+
+    /// Decodes the image into the start of `buf`, which may be larger than the image.
+    ///
+    /// A buffer of less than `total_bytes()` is a parameter error, see `fits_in`. The bytes after
+    /// the image are left untouched.
+    fn read_image(self, buf: &mut [u8]) -> ImageResult<()> {
+        if !self.fits_in(buf.len() as u64) {
+            return Err(buffer_too_small());
+        }
+
+        let len = self.total_bytes() as usize;
+        self.fill_image(&mut buf[..len]);
+        Ok(())
+    }
+}
+
+impl<'a, R: 'a + Read> AnimationDecoder<'a> for WebPDecoder<R> {
+    /// Returns the frames as composited on the canvas, which starts out with the background
+    /// color set by `set_background_color`.
+    ///
+    /// A still image is a single frame without delay.
+    fn into_frames(self) -> Frames<'a> {
+        if self.frames.is_empty() {
+            let frame = self.to_rgba_image().map(animation::Frame::new);
+            return Frames::new(Box::new(std::iter::once(frame)));
+        }
+
+        let extended = self.extended.unwrap_or_default();
+        let background = self.background_color();
+        let mut canvas = Canvas::new(extended.canvas_width, extended.canvas_height, background);
+        let (premultiply, rounding, upsampling) = (self.premultiply, self.rounding, self.upsampling);
+        let min_duration = self.min_frame_duration;
+
+        let frames = self.frames.into_iter().map(move |data| {
+            let frame = FrameChunks::parse(&data)?.decode()?;
+            canvas.draw(&frame, upsampling)?;
+
+            let mut image = canvas.image().clone();
+            if premultiply {
+                premultiply_image(&mut image, rounding);
+            }
+
+            let delay = Delay::from_numer_denom_ms(cmp::max(frame.header.duration, min_duration), 1);
+            Ok(animation::Frame::from_parts(image, 0, 0, delay))
+        });
+
+        Frames::new(Box::new(frames))
+    }
+}
+
+/// Reduces `image` by `factor` in both dimensions, averaging each block of pixels.
+///
+/// The blocks at the right and bottom edges may be smaller, as the result is rounded up.
+fn reduce<P>(image: &ImageBuffer<P, Vec<u8>>, factor: u32) -> ImageBuffer<P, Vec<u8>>
+where
+    P: Pixel<Subpixel = u8> + 'static,
+{
+    if factor == 1 {
+        return image.clone();
+    }
+
+    let channels = usize::from(P::CHANNEL_COUNT);
+    let width = (image.width() + factor - 1) / factor;
+    let height = (image.height() + factor - 1) / factor;
+    let pixels = width as usize * height as usize;
+
+    let (mut sums, mut counts) = (vec![0u32; channels * pixels], vec![0u32; pixels]);
+    for (x, y, pixel) in image.enumerate_pixels() {
+        let i = (y / factor) as usize * width as usize + (x / factor) as usize;
+        counts[i] += 1;
+        for (sum, &sample) in sums[channels * i..][..channels].iter_mut().zip(pixel.channels()) {
+            *sum += u32::from(sample);
+        }
+    }
+
+    let data = sums
+        .iter()
+        .enumerate()
+        .map(|(i, &sum)| {
+            let count = counts[i / channels];
+            ((sum + count / 2) / count) as u8
+        })
+        .collect();
+    ImageBuffer::from_raw(width, height, data).expect("The buffer has the size of the image")
+}
+
+/// Resamples `image` to `width` by `height` pixels, unless it already has that size.
+fn resize_to<P>(
+    image: ImageBuffer<P, Vec<u8>>,
+    width: u32,
+    height: u32,
+    filter: FilterType,
+) -> ImageBuffer<P, Vec<u8>>
+where
+    P: Pixel<Subpixel = u8> + 'static,
+{
+    if image.dimensions() == (width, height) {
+        return image;
+    }
+
+    imageops::resize(&image, width, height, filter)
+}
+
+/// Draws frame `target` of the animation of `frames` on a new `canvas`, after the frames since
+/// the last key frame before it. Returns the canvas with the duration of the frame.
+fn draw_from_key_frame(
+    frames: &[Vec<u8>],
+    target: usize,
+    mut canvas: Canvas,
+    upsampling: UpsampleFilter,
+) -> ImageResult<(RgbaImage, u32)> {
+    let (width, height) = canvas.image().dimensions();
+    let mut first = target;
+    loop {
+        let previous = match first {
+            0 => None,
+            _ => Some(FrameHeader::parse(&frames[first - 1])?),
+        };
+        if FrameChunks::parse(&frames[first])?.is_key_frame(previous.as_ref(), width, height) {
+            break;
+        }
+        first -= 1;
+    }
+
+    let mut duration = 0;
+    for frame in &frames[first..=target] {
+        let frame = FrameChunks::parse(frame)?.decode()?;
+        canvas.draw(&frame, upsampling)?;
+        duration = frame.header.duration;
+    }
+
+    Ok((canvas.into_image(), duration))
+}
+
+/// Premultiplies the color channels of `image` by its alpha channel.
+fn premultiply_image(image: &mut RgbaImage, rounding: PremultiplyRounding) {
+    for pixel in image.pixels_mut() {
+        let alpha = pixel[3];
+        for channel in &mut pixel.0[..3] {
+            *channel = rounding.premultiply(*channel, alpha);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "benchmarks")]
+    extern crate test;
+    use super::*;
+    #[cfg(feature = "benchmarks")]
+    use test::Bencher;
+
+    /// Wraps `data` into a RIFF chunk, including the padding byte of uneven chunks.
+    fn chunk(fourcc: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut chunk = fourcc.to_vec();
+        chunk.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        chunk.extend_from_slice(data);
+        if data.len() % 2 != 0 {
+            chunk.push(0);
+        }
+        chunk
+    }
+
+    /// Assembles a complete WebP file from its chunks.
+    fn riff(chunks: &[Vec<u8>]) -> Vec<u8> {
+        let payload: Vec<u8> = chunks.concat();
+        let mut data = b"RIFF".to_vec();
+        data.extend_from_slice(&(payload.len() as u32 + 4).to_le_bytes());
+        data.extend_from_slice(b"WEBP");
+        data.extend_from_slice(&payload);
+        data
+    }
+
+    /// A `VP8X` chunk with the given flags and canvas size.
+    fn vp8x(flags: u8, width: u32, height: u32) -> Vec<u8> {
+        let mut data = vec![flags, 0, 0, 0];
+        data.extend_from_slice(&(width - 1).to_le_bytes()[..3]);
+        data.extend_from_slice(&(height - 1).to_le_bytes()[..3]);
+        chunk(b"VP8X", &data)
+    }
+
+    #[test]
+    fn decode_and_resize() {
+        let data = riff(&[vp8()]);
+        let rgb = WebPDecoder::new(Cursor::new(&data)).unwrap().to_rgb_image().unwrap();
+
+        let decoder = WebPDecoder::new(Cursor::new(&data)).unwrap();
+        match decoder.decode_and_resize(20, 10, FilterType::Triangle).unwrap() {
+            DynamicImage::ImageRgb8(image) => assert_eq!(image.dimensions(), (20, 10)),
+            _ => panic!("expected an RGB image"),
+        }
+
+        // The image is returned as decoded at its own size.
+        let decoder = WebPDecoder::new(Cursor::new(&data)).unwrap();
+        match decoder.decode_and_resize(100, 100, FilterType::Lanczos3).unwrap() {
+            DynamicImage::ImageRgb8(image) => assert_eq!(image.into_raw(), rgb.into_raw()),
+            _ => panic!("expected an RGB image"),
+        }
+
+        let decoder = WebPDecoder::new(Cursor::new(animation())).unwrap();
+        match decoder.decode_and_resize(50, 25, FilterType::Nearest) {
+            Ok(DynamicImage::ImageRgba8(image)) => assert_eq!(image.dimensions(), (50, 25)),
+            _ => panic!("expected an RGBA image"),
+        }
+
+        let decoder = WebPDecoder::new(Cursor::new(&data)).unwrap();
+        assert!(decoder.decode_and_resize(0, 10, FilterType::Triangle).is_err());
+    }
+
+    #[test]
+    fn reduce_by_blocks() {
+        let image = RgbImage::from_raw(3, 1, vec![0, 10, 20, 3, 11, 40, 7, 7, 7]).unwrap();
+        assert_eq!(reduce(&image, 1).into_raw(), image.clone().into_raw());
+
+        // The last column forms a block of its own.
+        let reduced = reduce(&image, 2);
+        assert_eq!(reduced.dimensions(), (2, 1));
+        assert_eq!(reduced.into_raw(), [2, 11, 30, 7, 7, 7]);
+    }
+
+    #[test]
+    fn sniff_features() {
+        let still = Features { animated: false, alpha: false, lossless: Some(false) };
+        assert_eq!(sniff(&riff(&[vp8()])[..21]), Some(still));
+
+        // The alpha hint of lossless images needs the header of the chunk.
+        let lossless = riff(&[chunk(b"VP8L", &[0x2f, 0, 0, 0, 0x10])]);
+        assert_eq!(sniff(&lossless[..24]), None);
+        let features = Features { animated: false, alpha: true, lossless: Some(true) };
+        assert_eq!(sniff(&lossless), Some(features));
+
+        let extended = riff(&[vp8x(0b0001_0000, 100, 100), chunk(b"ALPH", &[0]), vp8()]);
+        let features = Features { animated: false, alpha: true, lossless: None };
+        assert_eq!(sniff(&extended[..30]), Some(features));
+        assert_eq!(sniff(&extended[..34]), Some(Features { lossless: Some(false), ..features }));
+
+        let features = Features { animated: true, alpha: true, lossless: None };
+        assert_eq!(sniff(&animation()), Some(features));
+
+        assert_eq!(sniff(&extended[..20]), None);
+        assert_eq!(sniff(b"GIF89a"), None);
+    }
+
+    #[test]
+    #[cfg(feature = "png")]
+    fn transcode_to_png() {
+        for data in &[riff(&[vp8()]), animation()] {
+            let png = super::transcode_to_png(data).unwrap();
+            let transcoded = crate::load_from_memory_with_format(&png, ImageFormat::Png).unwrap();
+            let decoded = decode(data).unwrap();
+            assert_eq!(transcoded.color(), decoded.color());
+            assert_eq!(transcoded.to_bytes(), decoded.to_bytes());
+        }
+
+        assert!(super::transcode_to_png(b"GIF89a").is_err());
+    }
+
+    #[test]
+    fn canvas_size_limits() {
+        for &(width, height) in &[(1, 1), (16384, 1), (1, 16384), (16384, 16384)] {
+            let data = riff(&[vp8x(0, width, height), vp8()]);
+            assert!(WebPDecoder::new(Cursor::new(&data)).is_ok(), "{}x{}", width, height);
+        }
+
+        for &(width, height) in &[(16385, 1), (1, 16385), (1 << 24, 1 << 24)] {
+            let data = riff(&[vp8x(0, width, height), vp8()]);
+            match WebPDecoder::new(Cursor::new(&data)) {
+                Err(ImageError::Decoding(_)) => {}
+                _ => panic!("expected a canvas of {}x{} to be rejected", width, height),
+            }
+        }
+    }
+
+    /// The `VP8 ` chunk of a simple 100x100 lossy file.
+    fn vp8() -> Vec<u8> {
+        let data = std::fs::read("tests/images/webp/images/simple-gray.webp").unwrap();
+        data[12..].to_vec()
+    }
+
+    /// The `VP8 ` chunk of `vp8` with a byte appended to the image data, followed by padding.
+    fn odd_vp8() -> Vec<u8> {
+        let mut data = vp8()[8..].to_vec();
+        data.push(0);
+        chunk(b"VP8 ", &data)
+    }
+
+    const ANIMATION: u8 = 0b0000_0010;
+
+    /// Blending bit of the `ANMF` flags, which disables alpha blending when set.
+    const NO_BLEND: u8 = 0b10;
+    /// Disposal bit of the `ANMF` flags.
+    const DISPOSE: u8 = 0b01;
+
+    /// An `ANMF` chunk of a 100x100 frame at the given position.
+    fn anmf(x: u32, y: u32, duration: u32, flags: u8, chunks: &[Vec<u8>]) -> Vec<u8> {
+        let mut data = Vec::new();
+        for &value in &[x / 2, y / 2, 99, 99, duration] {
+            data.extend_from_slice(&value.to_le_bytes()[..3]);
+        }
+        data.push(flags);
+        data.extend_from_slice(&chunks.concat());
+        chunk(b"ANMF", &data)
+    }
+
+    #[test]
+    fn animation_flag_without_frames() {
+        let data = riff(&[vp8x(ANIMATION, 100, 100), vp8()]);
+
+        let decoder = WebPDecoder::new(Cursor::new(&data)).unwrap();
+        assert_eq!(decoder.dimensions(), (100, 100));
+
+        match WebPDecoder::with_strictness(Cursor::new(&data), true) {
+            Err(ImageError::Decoding(_)) => {}
+            _ => panic!("expected a decoding error in strict mode"),
+        }
+    }
+
+    #[test]
+    fn animation_frames_without_flag() {
+        let data = riff(&[vp8x(0, 100, 100), anmf(0, 0, 0, 0, &[vp8()])]);
+
+        let decoder = WebPDecoder::new(Cursor::new(&data)).unwrap();
+        assert_eq!(decoder.into_frames().count(), 1);
+
+        match WebPDecoder::with_strictness(Cursor::new(&data), true) {
+            Err(ImageError::Decoding(_)) => {}
+            _ => panic!("expected a decoding error in strict mode"),
+        }
+    }
+
+    #[test]
+    fn animation_flag_with_frames() {
+        let data = riff(&[vp8x(ANIMATION, 100, 100), anmf(0, 0, 0, 0, &[vp8()])]);
+
+        for &strict in &[false, true] {
+            let decoder = WebPDecoder::with_strictness(Cursor::new(&data), strict).unwrap();
+            assert_eq!(decoder.dimensions(), (100, 100));
+        }
+
+        // A still image next to the frames is only tolerated in non-strict mode.
+        let data = riff(&[vp8x(ANIMATION, 100, 100), anmf(0, 0, 0, 0, &[vp8()]), vp8()]);
+        assert!(WebPDecoder::new(Cursor::new(&data)).is_ok());
+        assert!(WebPDecoder::with_strictness(Cursor::new(&data), true).is_err());
+    }
+
+    /// A file whose first opaque frame is disposed after it is shown, and whose second frame is
+    /// half transparent and drawn next to it.
+    fn animation() -> Vec<u8> {
+        let mut alpha = vec![0];
+        alpha.extend_from_slice(&[128; 100 * 100]);
+
+        riff(&[
+            vp8x(ANIMATION | 0b0001_0000, 200, 100),
+            chunk(b"ANIM", &[0; 6]),
+            anmf(0, 0, 50, DISPOSE, &[vp8()]),
+            anmf(100, 0, 70, 0, &[chunk(b"ALPH", &alpha), vp8()]),
+            chunk(b"EXIF", &[1, 2, 3]),
+        ])
+    }
+
+    #[test]
+    fn animation_frames() {
+        let decoder = WebPDecoder::new(Cursor::new(riff(&[vp8()]))).unwrap();
+        let mut gray = vec![0; decoder.total_bytes() as usize];
+        decoder.read_image(&mut gray).unwrap();
+
+        let data = animation();
+        for &strict in &[false, true] {
+            let decoder = WebPDecoder::with_strictness(Cursor::new(&data), strict).unwrap();
+            let frames = decoder.into_frames().collect_frames().unwrap();
+            assert_eq!(frames.len(), 2);
+
+            let delays: Vec<_> = frames.iter().map(|frame| frame.delay()).collect();
+            assert_eq!(delays, [Delay::from_numer_denom_ms(50, 1), Delay::from_numer_denom_ms(70, 1)]);
+
+            for (frame, &visible) in frames.iter().zip(&[(255, 0), (0, 128)]) {
+                assert_eq!(frame.buffer().dimensions(), (200, 100));
+
+                for (x, y, pixel) in frame.buffer().enumerate_pixels() {
+                    let alpha = if x < 100 { visible.0 } else { visible.1 };
+                    assert_eq!(pixel[3], alpha);
+
+                    // Blending over the transparent canvas keeps the colors of the frame.
+                    if alpha != 0 {
+                        let i = 3 * (x % 100 + 100 * y) as usize;
+                        assert_eq!(&pixel.0[..3], &gray[i..i + 3]);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn reversed_animation_frames() {
+        let forward_and_reversed = |data: &[u8]| {
+            let forward = WebPDecoder::new(Cursor::new(data)).unwrap();
+            let mut forward = forward.into_frames().collect_frames().unwrap();
+            forward.reverse();
+            let reversed = WebPDecoder::new(Cursor::new(data)).unwrap();
+            let reversed = reversed.into_frames_reversed().collect_frames().unwrap();
+
+            assert_eq!(forward.len(), reversed.len());
+            for (forward, reversed) in forward.iter().zip(&reversed) {
+                assert_eq!(forward.delay(), reversed.delay());
+                assert_eq!(**forward.buffer(), **reversed.buffer());
+            }
+        };
+
+        // The second frame is blended next to the first, which has to be drawn again.
+        forward_and_reversed(&animation());
+
+        // Frames covering the whole canvas are key frames, unless blended with alpha.
+        let mut alpha = vec![0];
+        alpha.extend_from_slice(&[128; 100 * 100]);
+        forward_and_reversed(&riff(&[
+            vp8x(ANIMATION | 0b0001_0000, 100, 100),
+            anmf(0, 0, 10, 0, &[vp8()]),
+            anmf(0, 0, 20, NO_BLEND, &[chunk(b"ALPH", &alpha), vp8()]),
+            anmf(0, 0, 30, DISPOSE, &[chunk(b"ALPH", &alpha), vp8()]),
+            anmf(0, 0, 40, 0, &[chunk(b"ALPH", &alpha), vp8()]),
+        ]));
+
+        let data = riff(&[vp8()]);
+        let frames = WebPDecoder::new(Cursor::new(&data)).unwrap().into_frames_reversed();
+        assert_eq!(frames.collect_frames().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn frame_at_time() {
+        let frames = |data: &[u8]| {
+            let decoder = WebPDecoder::new(Cursor::new(data)).unwrap();
+            let frames = decoder.into_frames().collect_frames().unwrap();
+            frames.into_iter().map(|frame| frame.into_buffer().into_raw()).collect::<Vec<_>>()
+        };
+        let at = |data: &[u8], ms| {
+            let decoder = WebPDecoder::new(Cursor::new(data)).unwrap();
+            decoder.frame_at_time(ms).unwrap().into_raw()
+        };
+
+        // The frames are shown for 50 and 70 ms, looping endlessly.
+        let data = animation();
+        let expected = frames(&data);
+        for &(ms, frame) in &[(0, 0), (49, 0), (50, 1), (119, 1), (120, 0), (170, 1), (1210, 0), (1250, 1)] {
+            assert!(at(&data, ms) == expected[frame], "{} ms", ms);
+        }
+
+        // After two loops the last frame stays.
+        let mut looping = data.clone();
+        let anim = looping.windows(4).position(|window| window == b"ANIM").unwrap();
+        looping[anim + 12] = 2;
+        for &(ms, frame) in &[(120, 0), (239, 1), (240, 1), (10_000, 1)] {
+            assert!(at(&looping, ms) == expected[frame], "{} ms", ms);
+        }
+
+        let data = riff(&[vp8()]);
+        let still = WebPDecoder::new(Cursor::new(&data)).unwrap().to_rgba_image().unwrap();
+        assert!(at(&data, 1234) == still.into_raw());
+    }
+
+    #[test]
+    fn alpha_after_image_data() {
+        let mut alpha = vec![0];
+        alpha.extend((0..100 * 100).map(|i| i as u8));
+        let alpha_chunk = chunk(b"ALPH", &alpha);
+
+        let before = riff(&[vp8x(0b0001_0000, 100, 100), alpha_chunk.clone(), vp8()]);
+        let after = riff(&[vp8x(0b0001_0000, 100, 100), vp8(), alpha_chunk.clone()]);
+        let odd = riff(&[vp8x(0b0001_0000, 100, 100), odd_vp8(), alpha_chunk.clone()]);
+        let expected = WebPDecoder::new(Cursor::new(&before)).unwrap().to_rgba_image().unwrap();
+
+        for data in &[after, odd] {
+            for &strict in &[false, true] {
+                let decoder = WebPDecoder::with_strictness(Cursor::new(data), strict).unwrap();
+                assert_eq!(decoder.color_type(), color::ColorType::Rgba8);
+                assert_eq!(decoder.warnings(), [DecodeWarning::AlphaAfterImage]);
+                assert_eq!(decoder.bytes_consumed(), data.len() as u64);
+                let rgba = decoder.to_rgba_image().unwrap();
+                assert_eq!(rgba.into_raw(), expected.clone().into_raw());
+            }
+        }
+
+        // The alpha of animation frames is found in either order too.
+        let frames = |chunks: &[Vec<u8>]| {
+            let data = riff(&[vp8x(ANIMATION | 0b0001_0000, 100, 100), anmf(0, 0, 10, 0, chunks)]);
+            let decoder = WebPDecoder::new(Cursor::new(&data)).unwrap();
+            decoder.into_frames().collect_frames().unwrap().remove(0).into_buffer().into_raw()
+        };
+        assert_eq!(frames(&[vp8(), alpha_chunk.clone()]), frames(&[alpha_chunk, vp8()]));
+    }
+
+    #[test]
+    fn decode_still() {
+        let still = WebPDecoder::new(Cursor::new(riff(&[vp8()]))).unwrap().to_rgba_image().unwrap();
+
+        // The still image next to the frames is decoded instead of the first frame.
+        let mut alpha = vec![0];
+        alpha.extend_from_slice(&[0; 100 * 100]);
+        let header = vp8x(ANIMATION | 0b0001_0000, 100, 100);
+        let first = anmf(0, 0, 10, 0, &[chunk(b"ALPH", &alpha), vp8()]);
+        let second = anmf(0, 0, 10, 0, &[vp8()]);
+        let data = riff(&[header.clone(), first.clone(), vp8(), second.clone()]);
+
+        let decoder = WebPDecoder::new(Cursor::new(&data)).unwrap();
+        assert_eq!(decoder.warnings(), [DecodeWarning::IgnoredImageChunk]);
+        assert_eq!(decoder.decode_still().unwrap().into_raw(), still.clone().into_raw());
+
+        // Otherwise it is the first frame, here transparent.
+        let data = riff(&[header, first, second]);
+        let image = WebPDecoder::new(Cursor::new(&data)).unwrap().decode_still().unwrap();
+        assert!(image.pixels().all(|pixel| pixel[3] == 0));
+
+        let image = WebPDecoder::new(Cursor::new(riff(&[vp8()]))).unwrap().decode_still().unwrap();
+        assert_eq!(image.into_raw(), still.into_raw());
+    }
+
+    #[test]
+    fn collect_frames() {
+        let frames = WebPDecoder::new(Cursor::new(animation())).unwrap().collect_frames().unwrap();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].0, Duration::from_millis(50));
+        assert_eq!(frames[1].0, Duration::from_millis(70));
+
+        // Each image is the whole canvas, with the first frame disposed of under the second.
+        for (_, image) in &frames {
+            assert_eq!(image.dimensions(), (200, 100));
+        }
+        assert_eq!(frames[0].1.get_pixel(150, 50).0, [0; 4]);
+        assert_eq!(frames[1].1.get_pixel(50, 50).0, [0; 4]);
+        assert_eq!(frames[1].1.get_pixel(150, 50).0[3], 128);
+
+        let frames = WebPDecoder::new(Cursor::new(riff(&[vp8()]))).unwrap().collect_frames().unwrap();
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].0, Duration::from_millis(0));
+
+        let limits = Limits { max_frames: 1, ..Limits::default() };
+        let decoder = WebPDecoder::with_limits(Cursor::new(animation()), false, limits).unwrap();
+        match decoder.collect_frames() {
+            Err(ImageError::Limits(_)) => {}
+            _ => panic!("expected more frames than the limit to be a limit error"),
+        }
+    }
+
+    #[test]
+    fn decode_discard() {
+        let decoder = WebPDecoder::new(Cursor::new(animation())).unwrap();
+        decoder.decode_discard().unwrap();
+
+        let decoder = WebPDecoder::new(Cursor::new(riff(&[vp8()]))).unwrap();
+        decoder.decode_discard().unwrap();
+
+        // A later frame whose image data does not match its size is only found by decoding it.
+        let mut frame = anmf(0, 0, 10, 0, &[vp8()]);
+        frame[8 + 6] = 98;
+        let data = riff(&[vp8x(ANIMATION, 100, 100), anmf(0, 0, 10, 0, &[vp8()]), frame]);
+        WebPDecoder::validate(Cursor::new(&data)).unwrap();
+        let decoder = WebPDecoder::new(Cursor::new(&data)).unwrap();
+        assert!(decoder.decode_discard().is_err());
+    }
+
+    #[test]
+    fn min_frame_duration() {
+        let data = riff(&[
+            vp8x(ANIMATION, 100, 100),
+            anmf(0, 0, 0, 0, &[vp8()]),
+            anmf(0, 0, 10, 0, &[vp8()]),
+            anmf(0, 0, 200, 0, &[vp8()]),
+        ]);
+        let delays = |min, reversed| {
+            let mut decoder = WebPDecoder::new(Cursor::new(&data)).unwrap();
+            if let Some(min) = min {
+                decoder.set_min_frame_duration(min);
+            }
+            let frames = if reversed { decoder.into_frames_reversed() } else { decoder.into_frames() };
+            let frames = frames.collect_frames().unwrap();
+            frames.iter().map(|frame| frame.delay().numer_denom_ms().0).collect::<Vec<_>>()
+        };
+
+        assert_eq!(delays(None, false), [0, 10, 200]);
+        assert_eq!(delays(Some(100), false), [100, 100, 200]);
+        assert_eq!(delays(Some(100), true), [200, 100, 100]);
+    }
+
+    #[test]
+    fn animation_background() {
+        let data = riff(&[
+            vp8x(ANIMATION, 200, 100),
+            chunk(b"ANIM", &[10, 20, 30, 255, 0, 0]),
+            anmf(0, 0, 50, DISPOSE, &[vp8()]),
+            anmf(100, 0, 70, 0, &[vp8()]),
+        ]);
+
+        // The background is stored as BGRA, filling the canvas and the disposed first frame.
+        let background = |color: Option<[u8; 4]>| {
+            let mut decoder = WebPDecoder::new(Cursor::new(&data)).unwrap();
+            decoder.set_background_color(color);
+            let frames = decoder.into_frames().collect_frames().unwrap();
+            let before = frames[0].buffer().get_pixel(150, 50).0;
+            let after = frames[1].buffer().get_pixel(50, 50).0;
+            assert_eq!(before, after);
+            before
+        };
+
+        assert_eq!(background(None), [30, 20, 10, 255]);
+        assert_eq!(background(Some([1, 2, 3, 4])), [1, 2, 3, 4]);
+
+        let mut decoder = WebPDecoder::new(Cursor::new(&data)).unwrap();
+        decoder.set_background_color(Some([1, 2, 3, 4]));
+        let mut rgba = vec![0; decoder.total_bytes() as usize];
+        decoder.read_image(&mut rgba).unwrap();
+        assert_eq!(&rgba[4 * 150..4 * 151], &[1, 2, 3, 4]);
+
+        let data = riff(&[vp8x(ANIMATION, 200, 100), chunk(b"ANIM", &[0; 4])]);
+        assert!(WebPDecoder::new(Cursor::new(&data)).is_err());
+    }
+
+    #[test]
+    fn animation_dimensions() {
+        let data = riff(&[vp8x(ANIMATION, 200, 150), anmf(0, 0, 0, 0, &[vp8()])]);
+
+        let decoder = WebPDecoder::new(Cursor::new(&data)).unwrap();
+        assert_eq!(decoder.dimensions(), (200, 150));
+        assert_eq!(decoder.color_type(), color::ColorType::Rgba8);
+
+        // The first frame covers the top left corner of the otherwise transparent canvas.
+        let mut rgba = vec![0; decoder.total_bytes() as usize];
+        decoder.read_image(&mut rgba).unwrap();
+        for (i, pixel) in rgba.chunks(4).enumerate() {
+            let inside = i % 200 < 100 && i / 200 < 100;
+            assert_eq!(pixel[3], if inside { 255 } else { 0 });
+        }
+    }
+
+    #[test]
+    fn animated_frame_outside_the_canvas() {
+        let data = riff(&[
+            vp8x(ANIMATION, 200, 100),
+            anmf(0, 0, 0, 0, &[vp8()]),
+            anmf(100, 2, 0, NO_BLEND, &[vp8()]),
+        ]);
+
+        let decoder = WebPDecoder::new(Cursor::new(&data)).unwrap();
+        let frames: Vec<_> = decoder.into_frames().collect();
+        assert!(frames[0].is_ok());
+        assert!(frames[1].is_err());
+    }
+
+    #[test]
+    fn atlas() {
+        let decoder = WebPDecoder::new(Cursor::new(animation())).unwrap();
+        let (atlas, rects) = decoder.into_atlas().unwrap();
+
+        // Two frames are laid out in a single row.
+        assert_eq!(atlas.dimensions(), (400, 100));
+        assert_eq!(rects, [
+            (Rect { x: 0, y: 0, width: 200, height: 100 }, Delay::from_numer_denom_ms(50, 1)),
+            (Rect { x: 200, y: 0, width: 200, height: 100 }, Delay::from_numer_denom_ms(70, 1)),
+        ]);
+
+        let frames = WebPDecoder::new(Cursor::new(animation())).unwrap().into_frames();
+        for (frame, &(rect, _)) in frames.zip(&rects) {
+            let frame = frame.unwrap();
+            for (x, y, pixel) in frame.buffer().enumerate_pixels() {
+                assert_eq!(atlas.get_pixel(rect.x + x, rect.y + y), pixel);
+            }
+        }
+
+        // Five frames fill two rows of three columns.
+        let frames: Vec<_> = (0..5).map(|_| anmf(0, 0, 10, 0, &[vp8()])).collect();
+        let data = riff(&[&[vp8x(ANIMATION, 100, 100)][..], &frames].concat());
+        let (atlas, rects) = WebPDecoder::new(Cursor::new(&data)).unwrap().into_atlas().unwrap();
+        assert_eq!(atlas.dimensions(), (300, 200));
+        assert_eq!(rects[4].0, Rect { x: 100, y: 100, width: 100, height: 100 });
+
+        // A still image is a single frame.
+        let (atlas, rects) = WebPDecoder::new(Cursor::new(riff(&[vp8()]))).unwrap().into_atlas().unwrap();
+        assert_eq!(atlas.dimensions(), (100, 100));
+        assert_eq!(rects.len(), 1);
+    }
+
+    #[test]
+    fn still_image_without_flag() {
+        let data = riff(&[vp8x(0, 100, 100), vp8()]);
+
+        for &strict in &[false, true] {
+            let decoder = WebPDecoder::with_strictness(Cursor::new(&data), strict).unwrap();
+            assert_eq!(decoder.dimensions(), (100, 100));
+        }
+    }
+
+    #[test]
+    fn vp8_profile() {
+        let mut frame = vp8();
+        let decoder = WebPDecoder::new(Cursor::new(riff(&[frame.clone()]))).unwrap();
+        assert_eq!(decoder.vp8_profile(), 0);
+
+        // The version is stored in bits 1 to 3 of the frame tag following the chunk header.
+        frame[8] |= 3 << 1;
+        let decoder = WebPDecoder::new(Cursor::new(riff(&[frame]))).unwrap();
+        assert_eq!(decoder.vp8_profile(), 3);
+    }
+
+    #[test]
+    fn read_image_u16() {
+        let data = riff(&[vp8()]);
+
+        let decoder = WebPDecoder::new(Cursor::new(&data)).unwrap();
+        let mut narrow = vec![0; decoder.total_bytes() as usize];
+        decoder.read_image(&mut narrow).unwrap();
+
+        let decoder = WebPDecoder::new(Cursor::new(&data)).unwrap();
+        let mut wide = vec![0; decoder.total_bytes() as usize];
+        decoder.read_image_u16(&mut wide).unwrap();
+
+        for (&wide, &narrow) in wide.iter().zip(&narrow) {
+            assert_eq!(wide, u16::from(narrow) * 257);
+            assert_eq!(wide >> 8, u16::from(narrow));
+        }
+    }
+
+    #[test]
+    fn read_image_rgba16() {
+        for data in &[riff(&[vp8()]), animation()] {
+            let decoder = WebPDecoder::new(Cursor::new(data)).unwrap();
+            let rgba = decoder.to_rgba_image().unwrap();
+
+            let decoder = WebPDecoder::new(Cursor::new(data)).unwrap();
+            let mut wide = vec![0; rgba.len()];
+            decoder.read_image_rgba16(&mut wide).unwrap();
+
+            for (&wide, &narrow) in wide.iter().zip(rgba.iter()) {
+                assert_eq!(wide, u16::from(narrow) * 257);
+            }
+        }
+
+        // Images without alpha are opaque.
+        let decoder = WebPDecoder::new(Cursor::new(riff(&[vp8()]))).unwrap();
+        let mut wide = vec![0; 4 * 100 * 100];
+        decoder.read_image_rgba16(&mut wide).unwrap();
+        assert!(wide.chunks(4).all(|pixel| pixel[3] == 65535));
+    }
+
+    #[test]
+    fn read_image_into_larger_buffer() {
+        let data = riff(&[vp8()]);
+        let decoder = WebPDecoder::new(Cursor::new(&data)).unwrap();
+        let len = decoder.total_bytes() as usize;
+        assert!(decoder.fits_in(len as u64) && !decoder.fits_in(len as u64 - 1));
+
+        let mut expected = vec![0; len];
+        decoder.read_image(&mut expected).unwrap();
+
+        let mut buf = vec![7; len + 10];
+        WebPDecoder::new(Cursor::new(&data)).unwrap().read_image(&mut buf).unwrap();
+        assert_eq!(&buf[..len], &expected[..]);
+        assert_eq!(&buf[len..], &[7; 10]);
+
+        let mut short = vec![0; len - 1];
+        match WebPDecoder::new(Cursor::new(&data)).unwrap().read_image(&mut short) {
+            Err(ImageError::Parameter(_)) => {}
+            _ => panic!("expected a buffer too small for the image to be an error"),
+        }
+
+        let mut wide = vec![7; len + 10];
+        WebPDecoder::new(Cursor::new(&data)).unwrap().read_image_u16(&mut wide).unwrap();
+        assert!(wide[..len].iter().zip(&expected).all(|(&wide, &v)| wide == u16::from(v) * 257));
+        assert_eq!(&wide[len..], &[7; 10]);
+
+        let mut float = vec![7.0; len + 10];
+        WebPDecoder::new(Cursor::new(&data)).unwrap().read_image_f32(&mut float).unwrap();
+        assert!(float[..len].iter().zip(&expected).all(|(&f, &v)| f == f32::from(v) / 255.0));
+        assert_eq!(&float[len..], &[7.0; 10]);
+
+        let rgba = len / 3 * 4;
+        let mut rgba16 = vec![7; rgba + 10];
+        WebPDecoder::new(Cursor::new(&data)).unwrap().read_image_rgba16(&mut rgba16).unwrap();
+        assert!(rgba16[..rgba].chunks(4).all(|pixel| pixel[3] == 65535));
+        assert_eq!(&rgba16[rgba..], &[7; 10]);
+
+        let decoder = WebPDecoder::new(Cursor::new(&data)).unwrap();
+        assert!(decoder.read_image_u16(&mut wide[..len - 1]).is_err());
+        let decoder = WebPDecoder::new(Cursor::new(&data)).unwrap();
+        assert!(decoder.read_image_f32(&mut float[..len - 1]).is_err());
+        let decoder = WebPDecoder::new(Cursor::new(&data)).unwrap();
+        match decoder.read_image_rgba16(&mut rgba16[..rgba - 1]) {
+            Err(ImageError::Parameter(_)) => {}
+            _ => panic!("expected a buffer too small for the image to be an error"),
+        }
+    }
+
+    #[test]
+    fn validate() {
+        let data = riff(&[vp8()]);
+        assert!(WebPDecoder::validate(Cursor::new(&data)).is_ok());
+
+        // The image data itself is not decoded.
+        let mut corrupted = data.clone();
+        for byte in &mut corrupted[30..] {
+            *byte = 0xff;
+        }
+        assert!(WebPDecoder::validate(Cursor::new(&corrupted)).is_ok());
+
+        let mut signature = data.clone();
+        signature[8..12].copy_from_slice(b"WEBQ");
+        assert!(WebPDecoder::validate(Cursor::new(&signature)).is_err());
+
+        let mut start_code = data.clone();
+        start_code[23] = 0;
+        assert!(WebPDecoder::validate(Cursor::new(&start_code)).is_err());
+
+        let truncated = &data[..data.len() - 1];
+        assert!(WebPDecoder::validate(Cursor::new(truncated)).is_err());
+
+        let flags = riff(&[vp8x(ANIMATION, 100, 100), vp8()]);
+        assert!(WebPDecoder::validate(Cursor::new(&flags)).is_err());
+
+        let metadata = riff(&[vp8x(0, 100, 100), vp8(), chunk(b"EXIF", &[1, 2, 3])]);
+        assert!(WebPDecoder::validate(Cursor::new(&metadata)).is_ok());
+
+        // The chunks after the image data are walked as by a strict decoder.
+        let second = riff(&[vp8(), chunk(b"VP8L", &[0x2f, 0, 0, 0, 0])]);
+        assert!(WebPDecoder::validate(Cursor::new(&second)).is_err());
+        assert!(WebPDecoder::with_strictness(Cursor::new(&second), true).is_err());
+
+        // The padding of an uneven image chunk is skipped before the chunks after it.
+        let odd = riff(&[odd_vp8(), chunk(b"EXIF", &[1, 2, 3])]);
+        assert!(WebPDecoder::validate(Cursor::new(&odd)).is_ok());
+        let odd_second = riff(&[odd_vp8(), chunk(b"EXIF", &[1, 2, 3]), odd_vp8()]);
+        assert!(WebPDecoder::validate(Cursor::new(&odd_second)).is_err());
+
+        let canvas = riff(&[vp8x(0, 120, 100), vp8()]);
+        assert!(WebPDecoder::validate(Cursor::new(&canvas)).is_err());
+        assert!(WebPDecoder::with_strictness(Cursor::new(&canvas), true).is_err());
+    }
+
+    #[test]
+    fn read_dimensions() {
+        let mut simple = riff(&[vp8()]);
+        assert_eq!(WebPDecoder::read_dimensions(Cursor::new(&simple)).unwrap(), (100, 100));
+
+        // The image data is not decoded.
+        for byte in &mut simple[30..] {
+            *byte = 0xff;
+        }
+        assert_eq!(WebPDecoder::read_dimensions(Cursor::new(&simple)).unwrap(), (100, 100));
+
+        let extended = riff(&[vp8x(0, 300, 200), vp8()]);
+        assert_eq!(WebPDecoder::read_dimensions(Cursor::new(&extended)).unwrap(), (300, 200));
+
+        let bits: u32 = (640 - 1) | ((480 - 1) << 14);
+        let mut lossless = vec![0x2f];
+        lossless.extend_from_slice(&bits.to_le_bytes());
+        let lossless = riff(&[chunk(b"XYZW", &[1, 2, 3]), chunk(b"VP8L", &lossless)]);
+        assert_eq!(WebPDecoder::read_dimensions(Cursor::new(&lossless)).unwrap(), (640, 480));
+
+        assert!(WebPDecoder::read_dimensions(Cursor::new(&simple[..25])).is_err());
+    }
+
+    #[test]
+    fn alpha_plane() {
+        // A horizontal gradient, encoded with the horizontal filter as a constant difference.
+        let mut alpha = vec![1 << 2];
+        for _ in 0..100 {
+            alpha.push(0);
+            alpha.extend_from_slice(&[2; 99]);
+        }
+        let data = riff(&[vp8x(0b0001_0000, 100, 100), chunk(b"ALPH", &alpha), vp8()]);
+
+        let decoder = WebPDecoder::new(Cursor::new(&data)).unwrap();
+        assert_eq!(decoder.color_type(), color::ColorType::Rgba8);
+
+        let mut rgba = vec![0; decoder.total_bytes() as usize];
+        decoder.read_image(&mut rgba).unwrap();
+
+        let decoder = WebPDecoder::new(Cursor::new(riff(&[vp8()]))).unwrap();
+        let mut rgb = vec![0; decoder.total_bytes() as usize];
+        decoder.read_image(&mut rgb).unwrap();
+
+        for (i, (rgba, rgb)) in rgba.chunks(4).zip(rgb.chunks(3)).enumerate() {
+            assert_eq!(&rgba[..3], rgb);
+            assert_eq!(rgba[3], (i % 100 * 2) as u8);
+        }
+    }
+
+    #[test]
+    fn premultiplied_alpha() {
+        // Alpha values of 0, 2, 4, ... along each row.
+        let mut alpha = vec![1 << 2];
+        for _ in 0..100 {
+            alpha.push(0);
+            alpha.extend_from_slice(&[2; 99]);
+        }
+        let data = riff(&[vp8x(0b0001_0000, 100, 100), chunk(b"ALPH", &alpha), vp8()]);
+
+        let decode = |premultiply, rounding| {
+            let mut decoder = WebPDecoder::new(Cursor::new(&data)).unwrap();
+            decoder.set_premultiplied_alpha(premultiply);
+            if let Some(rounding) = rounding {
+                decoder.set_premultiply_rounding(rounding);
+            }
+            let mut rgba = vec![0; decoder.total_bytes() as usize];
+            decoder.read_image(&mut rgba).unwrap();
+            rgba
+        };
+
+        let straight = decode(false, None);
+        let nearest = decode(true, None);
+        assert_eq!(nearest, decode(true, Some(PremultiplyRounding::Nearest)));
+        let truncated = decode(true, Some(PremultiplyRounding::Truncate));
+
+        let pixels = straight.chunks(4).zip(nearest.chunks(4)).zip(truncated.chunks(4));
+        for ((straight, nearest), truncated) in pixels {
+            let alpha = u32::from(straight[3]);
+            assert_eq!((nearest[3], truncated[3]), (straight[3], straight[3]));
+
+            for c in 0..3 {
+                let product = u32::from(straight[c]) * alpha;
+                assert_eq!(u32::from(truncated[c]), product / 255);
+                assert_eq!(u32::from(nearest[c]), (f64::from(product) / 255.0).round() as u32);
+            }
+        }
+
+        // 128 * 129 / 255 is 64.75.
+        assert_eq!(PremultiplyRounding::Nearest.premultiply(128, 129), 65);
+        assert_eq!(PremultiplyRounding::Truncate.premultiply(128, 129), 64);
+        assert_eq!(PremultiplyRounding::Nearest.premultiply(255, 255), 255);
+        assert_eq!(PremultiplyRounding::Truncate.premultiply(255, 255), 255);
+    }
+
+    #[cfg(feature = "benchmarks")]
+    #[bench]
+    fn bench_large_chunk(b: &mut Bencher) {
+        // Trailing data of the last partition is ignored, so this decodes like the original.
+        let mut payload = vp8()[8..].to_vec();
+        payload.resize(payload.len() + (16 << 20), 0);
+        let data = riff(&[chunk(b"VP8 ", &payload)]);
+
+        b.iter(|| {
+            WebPDecoder::new(Cursor::new(&data)).unwrap();
+        });
+    }
+
+    #[test]
+    fn into_tiles() {
+        let data = riff(&[vp8()]);
+
+        let decoder = WebPDecoder::new(Cursor::new(&data)).unwrap();
+        let mut image = vec![0; decoder.total_bytes() as usize];
+        decoder.read_image(&mut image).unwrap();
+
+        let decoder = WebPDecoder::new(Cursor::new(&data)).unwrap();
+        let tiles: Vec<_> = decoder.into_tiles(32, 40).collect();
+        assert_eq!(tiles.len(), 4 * 3);
+
+        let (last, _) = tiles[tiles.len() - 1];
+        assert_eq!((last.column, last.row, last.x, last.y), (3, 2, 96, 80));
+        assert_eq!((last.width, last.height), (4, 20));
+
+        for (coord, tile) in tiles {
+            assert_eq!(tile.len(), coord.width as usize * coord.height as usize * 3);
+
+            for (dy, line) in tile.chunks(coord.width as usize * 3).enumerate() {
+                let start = ((coord.y as usize + dy) * 100 + coord.x as usize) * 3;
+                assert_eq!(line, &image[start..start + line.len()]);
+            }
+        }
+    }
+
+    #[test]
+    fn truncated_headers() {
+        let data = riff(&[vp8x(0, 100, 100), vp8()]);
+        let cuts = [
+            (0, "RIFF signature ends after 0 of 4 bytes"),
+            (2, "RIFF signature ends after 2 of 4 bytes"),
+            (4, "RIFF size ends after 0 of 4 bytes"),
+            (7, "RIFF size ends after 3 of 4 bytes"),
+            (10, "WEBP signature ends after 2 of 4 bytes"),
+            (12, "chunk fourcc ends after 0 of 4 bytes"),
+            (14, "chunk fourcc ends after 2 of 4 bytes"),
+            (17, "chunk length ends after 1 of 4 bytes"),
+            (30, "chunk fourcc ends after 0 of 4 bytes"),
+            (36, "chunk length ends after 2 of 4 bytes"),
+        ];
+
+        for &strict in &[false, true] {
+            for &(len, message) in &cuts {
+                match WebPDecoder::with_strictness(Cursor::new(&data[..len]), strict) {
+                    Err(ImageError::Decoding(err)) => {
+                        assert!(err.to_string().contains(message), "{}: {}", len, err)
+                    }
+                    _ => panic!("expected a file cut after {} bytes to be an error", len),
+                }
+            }
+        }
+
+        match WebPDecoder::read_dimensions(Cursor::new(&data[..17])) {
+            Err(ImageError::Decoding(err)) => assert!(err.to_string().contains("chunk length")),
+            _ => panic!("expected a truncated chunk length to be an error"),
+        }
+    }
+
+    #[test]
+    fn truncated_image_data() {
+        let data = riff(&[vp8()]);
+        let truncated = &data[..data.len() - 100];
+
+        match WebPDecoder::with_strictness(Cursor::new(truncated), true) {
+            Err(ImageError::Decoding(err)) => {
+                let expected = data.len() - 20;
+                let message = format!("expected {} bytes, got {}", expected, expected - 100);
+                assert!(err.to_string().contains(&message), "{}", err);
+            }
+            _ => panic!("expected truncation to be detected"),
+        }
+    }
+
+    #[test]
+    fn buffer_size_from_metadata() {
+        let alpha = [vec![0], vec![255; 100 * 100]].concat();
+        let with_alpha = riff(&[vp8x(0b0001_0000, 100, 100), chunk(b"ALPH", &alpha), vp8()]);
+        let without_alpha = riff(&[vp8x(0, 100, 100), vp8()]);
+
+        for &(data, color_type) in &[
+            (&with_alpha, color::ColorType::Rgba8),
+            (&without_alpha, color::ColorType::Rgb8),
+        ] {
+            let decoder = WebPDecoder::new(Cursor::new(data)).unwrap();
+            assert_eq!(decoder.color_type(), color_type);
+
+            let total_bytes = decoder.total_bytes();
+            assert_eq!(total_bytes, 100 * 100 * u64::from(color_type.bytes_per_pixel()));
+
+            let mut buf = vec![0; total_bytes as usize];
+            decoder.read_image(&mut buf).unwrap();
+
+            let mut contents = Vec::new();
+            let decoder = WebPDecoder::new(Cursor::new(data)).unwrap();
+            decoder.into_reader().unwrap().read_to_end(&mut contents).unwrap();
+            assert_eq!(contents, buf);
+        }
+    }
+
+    #[test]
+    fn concatenated_files() {
+        let first = riff(&[vp8(), chunk(b"EXIF", &[1, 2, 3, 4, 5])]);
+        let second = riff(&[vp8x(0, 100, 100), vp8(), chunk(b"XMP ", &[6, 7, 8])]);
+        let mut reader = Cursor::new([&first[..], &second[..]].concat());
+
+        WebPDecoder::new(&mut reader).unwrap();
+        assert_eq!(reader.position(), first.len() as u64);
+
+        let decoder = WebPDecoder::new(&mut reader).unwrap();
+        assert_eq!(decoder.dimensions(), (100, 100));
+        assert_eq!(reader.position(), (first.len() + second.len()) as u64);
+
+        // The padding of an uneven image chunk is skipped before the chunks after it.
+        let odd = riff(&[odd_vp8(), chunk(b"EXIF", &[1, 2, 3])]);
+        let mut reader = Cursor::new([&odd[..], &odd[..]].concat());
+        for i in 1..=2 {
+            let decoder = WebPDecoder::new(&mut reader).unwrap();
+            assert!(decoder.warnings().is_empty());
+            assert_eq!(reader.position(), i * odd.len() as u64);
+        }
+
+        // A trailing chunk claiming more bytes than the RIFF payload holds is cut at its end.
+        let mut lying = first.clone();
+        let at = 12 + vp8().len() + 4;
+        lying[at..at + 4].copy_from_slice(&1000u32.to_le_bytes());
+        let mut reader = Cursor::new([&lying[..], &second[..]].concat());
+
+        let decoder = WebPDecoder::new(&mut reader).unwrap();
+        assert_eq!(decoder.warnings(), [DecodeWarning::Truncated]);
+        assert_eq!(reader.position(), lying.len() as u64);
+        assert_eq!(WebPDecoder::new(&mut reader).unwrap().dimensions(), (100, 100));
+
+        let reader = Cursor::new([&lying[..], &second[..]].concat());
+        assert!(WebPDecoder::with_strictness(reader, true).is_err());
+    }
+
+    #[test]
+    fn borrowed_reader() {
+        let data = riff(&[vp8x(0b0010_0000, 100, 100), chunk(b"ICCP", &[1, 2]), vp8()]);
+        let mut reader = Cursor::new([&data[..], b"trailing data"].concat());
+
+        let decoder = WebPDecoder::new(&mut reader).unwrap();
+        let mut buf = vec![0; decoder.total_bytes() as usize];
+        decoder.read_image(&mut buf).unwrap();
+
+        let mut trailer = Vec::new();
+        reader.read_to_end(&mut trailer).unwrap();
+        assert_eq!(trailer, b"trailing data");
+    }
+
+    #[test]
+    fn endless_empty_chunks() {
+        let data = riff(&[chunk(b"JUNK", &[]), chunk(b"JUNK", &[])]);
+
+        for &strict in &[false, true] {
+            // Anything after the RIFF payload reads as a zero-length chunk.
+            let reader = Cursor::new(&data).chain(io::repeat(0));
+            match WebPDecoder::with_strictness(reader, strict) {
+                Err(ImageError::Decoding(_)) => {}
+                _ => panic!("expected a decoding error"),
+            }
+        }
+    }
+
+    #[test]
+    fn decode_rows() {
+        let mut alpha = vec![0];
+        alpha.extend((0..100 * 100).map(|i| i as u8));
+        let with_alpha = riff(&[vp8x(0b0001_0000, 100, 100), chunk(b"ALPH", &alpha), vp8()]);
+
+        for data in &[riff(&[vp8()]), with_alpha, animation()] {
+            let decoder = WebPDecoder::new(Cursor::new(data)).unwrap();
+            let mut expected = vec![0; decoder.total_bytes() as usize];
+            decoder.read_image(&mut expected).unwrap();
+
+            let decoder = WebPDecoder::new(Cursor::new(data)).unwrap();
+            let mut rows = Vec::new();
+            let mut next = 0;
+            decoder.decode_rows(|y, row| {
+                assert_eq!(y, next);
+                next += 1;
+                rows.extend_from_slice(row);
+            }).unwrap();
+
+            assert_eq!(rows, expected);
+        }
+    }
+
+    #[test]
+    fn upsampling() {
+        let data = std::fs::read("tests/images/webp/images/simple-rgb.webp").unwrap();
+        let decode = |filter: Option<UpsampleFilter>| {
+            let mut decoder = WebPDecoder::new(Cursor::new(&data)).unwrap();
+            if let Some(filter) = filter {
+                decoder.set_upsampling(filter);
+            }
+            let mut rgb = vec![0; decoder.total_bytes() as usize];
+            decoder.read_image(&mut rgb).unwrap();
+            rgb
+        };
+
+        let point = decode(None);
+        assert_eq!(decode(Some(UpsampleFilter::Point)), point);
+
+        let bilinear = decode(Some(UpsampleFilter::Bilinear));
+        let sharp = decode(Some(UpsampleFilter::Sharp));
+        assert_ne!(bilinear, point);
+        assert_ne!(sharp, bilinear);
+        assert_eq!((bilinear.len(), sharp.len()), (point.len(), point.len()));
+    }
+
+    #[test]
+    fn max_chunks() {
+        let mut chunks = vec![chunk(b"XYZW", &[]); 1000];
+        chunks.push(vp8());
+        let data = riff(&chunks);
+
+        let limits = |max_chunks| Limits { max_chunks, ..Limits::default() };
+        assert!(WebPDecoder::new(Cursor::new(&data)).is_ok());
+        assert!(WebPDecoder::with_limits(Cursor::new(&data), false, limits(1001)).is_ok());
+
+        match WebPDecoder::with_limits(Cursor::new(&data), false, limits(1000)) {
+            Err(ImageError::Decoding(_)) => {}
+            _ => panic!("expected too many chunks to be a decoding error"),
+        }
+
+        // The trailing chunks that strict mode checks count as well.
+        let mut chunks = vec![vp8()];
+        chunks.extend(vec![chunk(b"XYZW", &[]); 10]);
+        let data = riff(&chunks);
+        assert!(WebPDecoder::with_limits(Cursor::new(&data), true, limits(11)).is_ok());
+        assert!(WebPDecoder::with_limits(Cursor::new(&data), true, limits(10)).is_err());
+    }
+
+    #[test]
+    fn to_images() {
+        let mut alpha = vec![0];
+        alpha.extend((0..100 * 100).map(|i| i as u8));
+        let with_alpha = riff(&[vp8x(0b0001_0000, 100, 100), chunk(b"ALPH", &alpha), vp8()]);
+
+        for data in &[riff(&[vp8()]), with_alpha] {
+            let decoder = WebPDecoder::new(Cursor::new(data)).unwrap();
+            let has_alpha = decoder.color_type().has_alpha();
+            let mut expected = vec![0; decoder.total_bytes() as usize];
+            decoder.read_image(&mut expected).unwrap();
+            let channels = if has_alpha { 4 } else { 3 };
+
+            let rgba = WebPDecoder::new(Cursor::new(data)).unwrap().to_rgba_image().unwrap();
+            let rgb = WebPDecoder::new(Cursor::new(data)).unwrap().to_rgb_image().unwrap();
+            let luma = WebPDecoder::new(Cursor::new(data)).unwrap().to_luma_image().unwrap();
+            assert_eq!(rgba.dimensions(), (100, 100));
+            assert_eq!(rgb.dimensions(), (100, 100));
+            assert_eq!(luma.dimensions(), (100, 100));
+
+            for (i, pixel) in expected.chunks(channels).enumerate() {
+                let (x, y) = (i as u32 % 100, i as u32 / 100);
+                let alpha = if has_alpha { pixel[3] } else { 255 };
+                assert_eq!(rgba.get_pixel(x, y).0, [pixel[0], pixel[1], pixel[2], alpha]);
+                assert_eq!(rgb.get_pixel(x, y).0, [pixel[0], pixel[1], pixel[2]]);
+            }
+
+            assert_eq!(luma.into_raw(), imageops::grayscale(&rgb).into_raw());
+        }
+    }
+
+    #[test]
+    fn sharp_alpha_edges() {
+        // A checkerboard of single pixels, finer than the chroma planes can resolve.
+        let mask: Vec<u8> = (0..100 * 100)
+            .map(|i| if (i % 100 + i / 100) % 2 == 0 { 255 } else { 0 })
+            .collect();
+        let mut alpha = vec![0];
+        alpha.extend_from_slice(&mask);
+        let data = riff(&[vp8x(0b0001_0000, 100, 100), chunk(b"ALPH", &alpha), vp8()]);
+
+        let mut decoder = WebPDecoder::new(Cursor::new(&data)).unwrap();
+        decoder.set_upsampling(UpsampleFilter::Bilinear);
+        let mut rgba = vec![0; decoder.total_bytes() as usize];
+        decoder.read_image(&mut rgba).unwrap();
+        assert!(rgba.chunks(4).map(|pixel| pixel[3]).eq(mask.iter().cloned()));
+
+        let frame = anmf(0, 0, 10, NO_BLEND, &[chunk(b"ALPH", &alpha), vp8()]);
+        let data = riff(&[vp8x(ANIMATION, 100, 100), frame]);
+        let mut decoder = WebPDecoder::new(Cursor::new(&data)).unwrap();
+        decoder.set_upsampling(UpsampleFilter::Bilinear);
+        let frame = decoder.into_frames().next().unwrap().unwrap();
+        assert!(frame.buffer().pixels().map(|pixel| pixel[3]).eq(mask.iter().cloned()));
+    }
+
+    #[test]
+    fn dump_structure() {
+        let data = riff(&[vp8x(0b0001_0000, 100, 100), chunk(b"ALPH", &[0; 3]), vp8()]);
+        let dump = WebPDecoder::dump_structure(Cursor::new(&data)).unwrap();
+        assert_eq!(dump, format!(
+            "RIFF WEBP, {} bytes\n\
+             VP8X at 12, 10 bytes: flags [alpha], canvas 100x100\n\
+             ALPH at 30, 3 bytes\n\
+             VP8  at 42, {} bytes\n",
+            data.len() - 8,
+            vp8().len() - 8,
+        ));
+
+        let dump = WebPDecoder::dump_structure(Cursor::new(&animation())).unwrap();
+        assert_eq!(
+            dump,
+            "RIFF WEBP, 12554 bytes\n\
+             VP8X at 12, 10 bytes: flags [alpha, animation], canvas 200x100\n\
+             ANIM at 30, 6 bytes\n\
+             ANMF at 44, 1240 bytes: frame 100x100 at (0, 0), 50 ms, blend, dispose\n  \
+             VP8  at 68, 1216 bytes\n\
+             ANMF at 1292, 11250 bytes: frame 100x100 at (100, 0), 70 ms, blend\n  \
+             ALPH at 1316, 10001 bytes\n  \
+             VP8  at 11326, 1216 bytes\n\
+             EXIF at 12550, 3 bytes\n"
+        );
+
+        // A truncated chunk ends the listing with an error.
+        let mut data = riff(&[vp8x(0, 100, 100), chunk(b"\x01xyz", &[0; 100])]);
+        data.truncate(80);
+        let dump = WebPDecoder::dump_structure(Cursor::new(&data)).unwrap();
+        let lines: Vec<_> = dump.lines().collect();
+        assert!(lines[2].starts_with("error at 30: "));
+        assert!(WebPDecoder::dump_structure(Cursor::new(b"RIFF")).is_err());
+    }
+
+    #[test]
+    fn inflated_first_partition() {
+        let mut inflated = vp8();
+        // The largest size the 19 bits of the frame tag can hold, keeping the other fields.
+        inflated[8] |= 0b1110_0000;
+        inflated[9] = 0xff;
+        inflated[10] = 0xff;
+
+        let frame = anmf(0, 0, 10, 0, &[inflated.clone()]);
+        let animated = riff(&[vp8x(ANIMATION, 100, 100), frame]);
+
+        for data in &[riff(&[inflated]), animated] {
+            match WebPDecoder::new(Cursor::new(data)) {
+                Err(ImageError::Decoding(err)) => {
+                    assert!(err.to_string().contains("First partition of 524287 bytes"))
+                }
+                _ => panic!("expected an inflated first partition to be a decoding error"),
+            }
+            assert!(WebPDecoder::validate(Cursor::new(data)).is_err());
+        }
+    }
+
+    #[test]
+    fn to_luma_alpha_image() {
+        // A gray mask with a hard edge between the transparent and opaque halves.
+        let mut alpha = vec![0];
+        alpha.extend((0..100 * 100).map(|i| if i % 100 < 50 { 0 } else { 255 }));
+        let data = riff(&[vp8x(0b0001_0000, 100, 100), chunk(b"ALPH", &alpha), vp8()]);
+
+        let luma = WebPDecoder::new(Cursor::new(&data)).unwrap().to_luma_image().unwrap();
+        let masked = WebPDecoder::new(Cursor::new(&data)).unwrap().to_luma_alpha_image().unwrap();
+        assert_eq!(masked.dimensions(), (100, 100));
+        for ((x, _, pixel), luma) in masked.enumerate_pixels().zip(luma.pixels()) {
+            assert_eq!(pixel.0, [luma[0], if x < 50 { 0 } else { 255 }]);
+        }
+
+        let opaque = riff(&[vp8()]);
+        let masked = WebPDecoder::new(Cursor::new(&opaque)).unwrap().to_luma_alpha_image().unwrap();
+        assert!(masked.pixels().all(|pixel| pixel[1] == 255));
+    }
+
+    #[test]
+    fn warnings() {
+        let decoder = WebPDecoder::new(Cursor::new(riff(&[vp8()]))).unwrap();
+        assert!(decoder.warnings().is_empty());
+
+        // A reserved bit and the animation flag without frames, and a second image whose
+        // chunk is cut short by the end of the file.
+        let mut data = riff(&[vp8x(ANIMATION | 0b1000_0000, 100, 100), vp8(), vp8()]);
+        data.truncate(data.len() - 10);
+        let decoder = WebPDecoder::new(Cursor::new(&data)).unwrap();
+        assert_eq!(decoder.warnings(), [
+            DecodeWarning::ReservedBits,
+            DecodeWarning::AnimationFlagWithoutFrames,
+            DecodeWarning::IgnoredImageChunk,
+            DecodeWarning::Truncated,
+        ]);
+
+        let data = riff(&[vp8x(0, 50, 100), vp8()]);
+        let decoder = WebPDecoder::new(Cursor::new(&data)).unwrap();
+        assert_eq!(decoder.dimensions(), (100, 100));
+        assert_eq!(decoder.warnings(), [DecodeWarning::CanvasSizeMismatch {
+            canvas: (50, 100),
+            image: (100, 100),
+        }]);
+
+        let data = riff(&[vp8x(0, 200, 100), anmf(0, 0, 10, 0, &[vp8()]), vp8()]);
+        let decoder = WebPDecoder::new(Cursor::new(&data)).unwrap();
+        assert_eq!(decoder.warnings(), [
+            DecodeWarning::FramesWithoutAnimationFlag,
+            DecodeWarning::IgnoredImageChunk,
+        ]);
+
+        let limits = Limits { treat_warnings_as_errors: true, ..Limits::default() };
+        let data = riff(&[vp8x(0b1000_0000, 100, 100), vp8()]);
+        match WebPDecoder::with_limits(Cursor::new(&data), false, limits) {
+            Err(ImageError::Decoding(err)) => {
+                let warning = err.source().and_then(|err| err.downcast_ref::<DecodeWarning>());
+                assert_eq!(warning, Some(&DecodeWarning::ReservedBits));
+            }
+            _ => panic!("expected the warning to be a decoding error"),
+        }
+        assert!(WebPDecoder::with_limits(Cursor::new(riff(&[vp8()])), false, limits).is_ok());
+    }
+
+    #[test]
+    fn scale_to_canvas() {
+        let data = riff(&[vp8x(0, 50, 120), vp8()]);
+        match WebPDecoder::with_strictness(Cursor::new(&data), true) {
+            Err(ImageError::Decoding(_)) => {}
+            _ => panic!("expected a canvas size mismatch to be a decoding error in strict mode"),
+        }
+
+        let decoder = WebPDecoder::new(Cursor::new(riff(&[vp8()]))).unwrap();
+        let expected = imageops::resize(&decoder.to_rgb_image().unwrap(), 50, 120, FilterType::Triangle);
+
+        let mut decoder = WebPDecoder::new(Cursor::new(&data)).unwrap();
+        decoder.set_scale_to_canvas(true);
+        assert_eq!(decoder.dimensions(), (50, 120));
+        assert_eq!(decoder.total_bytes(), 3 * 50 * 120);
+        let mut rows = Vec::new();
+        decoder.decode_rows(|_, row| rows.extend_from_slice(row)).unwrap();
+        assert!(rows == *expected);
+
+        let mut decoder = WebPDecoder::new(Cursor::new(&data)).unwrap();
+        decoder.set_scale_to_canvas(true);
+        assert!(*decoder.to_rgb_image().unwrap() == *expected);
+
+        // Images with the size of the canvas are decoded as they are.
+        let mut decoder = WebPDecoder::new(Cursor::new(riff(&[vp8x(0, 100, 100), vp8()]))).unwrap();
+        decoder.set_scale_to_canvas(true);
+        assert_eq!(decoder.dimensions(), (100, 100));
+    }
+
+    #[test]
+    fn partial_image() {
+        let full = vp8();
+        let data = riff(&[full[..8 + (full.len() - 8) * 3 / 4].to_vec()]);
+
+        let decoder = WebPDecoder::new(Cursor::new(riff(&[vp8()]))).unwrap();
+        let mut expected = vec![0; decoder.total_bytes() as usize];
+        decoder.read_image(&mut expected).unwrap();
+
+        let decoder = WebPDecoder::new(Cursor::new(&data)).unwrap();
+        let rows = match decoder.warnings().first() {
+            Some(&DecodeWarning::PartialImage { rows }) => rows as usize,
+            _ => panic!("expected a warning for the partial image"),
+        };
+        assert!(rows > 0 && rows < 100);
+
+        // The decoded rows are kept, apart from the loop filter across their lower edge, and the
+        // rest of the image is gray.
+        let mut rgb = vec![0; decoder.total_bytes() as usize];
+        decoder.read_image(&mut rgb).unwrap();
+        assert_eq!(rgb[..300 * (rows - 6)], expected[..300 * (rows - 6)]);
+        assert!(rgb[300 * rows..].chunks(3).all(|pixel| pixel == &rgb[300 * rows..][..3]));
+
+        match WebPDecoder::with_strictness(Cursor::new(&data), true) {
+            Err(ImageError::Decoding(_)) => {}
+            _ => panic!("expected a decoding error in strict mode"),
+        }
+    }
+
+    #[test]
+    fn empty_icc_profile() {
+        let data = riff(&[vp8x(0b0010_0000, 100, 100), chunk(b"ICCP", &[]), vp8()]);
+        let decoder = WebPDecoder::new(Cursor::new(&data)).unwrap();
+        assert_eq!(decoder.icc_profile(), None);
+        assert!(!decoder.is_color_managed());
+        assert_eq!(decoder.warnings(), [DecodeWarning::EmptyIccProfile]);
+
+        match WebPDecoder::with_strictness(Cursor::new(&data), true) {
+            Err(ImageError::Decoding(_)) => {}
+            _ => panic!("expected a decoding error in strict mode"),
+        }
+    }
+
+    #[test]
+    fn icc_profile() {
+        let profile = [7u8; 33];
+        let data = riff(&[vp8x(0b0010_0000, 100, 100), chunk(b"ICCP", &profile), vp8()]);
+        let decoder = WebPDecoder::new(Cursor::new(&data)).unwrap();
+        assert!(decoder.is_color_managed());
+        assert_eq!(decoder.icc_profile(), Some(&profile[..]));
+
+        // The pixels are not transformed.
+        let mut tagged = vec![0; decoder.total_bytes() as usize];
+        decoder.read_image(&mut tagged).unwrap();
+
+        let decoder = WebPDecoder::new(Cursor::new(riff(&[vp8()]))).unwrap();
+        assert!(!decoder.is_color_managed());
+        assert_eq!(decoder.icc_profile(), None);
+        let mut untagged = vec![0; decoder.total_bytes() as usize];
+        decoder.read_image(&mut untagged).unwrap();
+        assert_eq!(tagged, untagged);
+    }
+
+    #[test]
+    fn bytes_consumed() {
+        let data = riff(&[vp8x(0, 100, 100), vp8(), chunk(b"EXIF", &[1, 2, 3])]);
+        let mut stream = data.clone();
+        stream.extend_from_slice(b"trailing data");
+
+        for &strict in &[false, true] {
+            let decoder = WebPDecoder::with_strictness(Cursor::new(&stream), strict).unwrap();
+            assert_eq!(decoder.bytes_consumed(), data.len() as u64);
+
+            // Only the bytes that are actually present are counted.
+            let cut = &data[..data.len() - 3];
+            let decoder = WebPDecoder::with_strictness(Cursor::new(cut), strict).unwrap();
+            assert_eq!(decoder.bytes_consumed(), cut.len() as u64);
+        }
+    }
+
+    #[test]
+    fn lossy_and_lossless_chunks() {
+        let lossless = chunk(b"VP8L", &[0x2f, 0, 0, 0, 0]);
+
+        let lossy_first = riff(&[vp8(), lossless.clone()]);
+        assert!(WebPDecoder::new(Cursor::new(&lossy_first)).is_ok());
+
+        let lossless_first = riff(&[lossless, vp8()]);
+        match WebPDecoder::new(Cursor::new(&lossless_first)) {
+            Err(ImageError::Unsupported(_)) => {}
+            _ => panic!("expected lossless images to be unsupported"),
+        }
+
+        for data in &[lossy_first, lossless_first] {
+            match WebPDecoder::with_strictness(Cursor::new(data), true) {
+                Err(ImageError::Decoding(_)) => {}
+                _ => panic!("expected a decoding error in strict mode"),
+            }
+        }
+    }
+
+    #[test]
+    fn yuv_planes() {
+        let data = riff(&[vp8()]);
+
+        let frame = WebPDecoder::new(Cursor::new(&data)).unwrap().into_yuv420().unwrap();
+        let (_, width, height, _) = frame.y_plane();
+        assert_eq!((width, height), (100, 100));
+        let (u, width, height, stride) = frame.u_plane();
+        assert_eq!((width, height, stride), (50, 50, 50));
+
+        for &filter in &[UpsampleFilter::Point, UpsampleFilter::Bilinear, UpsampleFilter::Sharp] {
+            let mut decoder = WebPDecoder::new(Cursor::new(&data)).unwrap();
+            decoder.set_upsampling(filter);
+            let (y444, u444, v444) = decoder.into_yuv444().unwrap();
+            assert_eq!((y444.len(), u444.len(), v444.len()), (10000, 10000, 10000));
+            assert_eq!(y444, frame.y_plane().0);
+            if filter == UpsampleFilter::Point {
+                assert!((0..10000).all(|i| u444[i] == u[(i / 200) * 50 + i % 100 / 2]));
+            }
+
+            // Converting the samples gives the pixels of `read_image` with the same filter.
+            let mut decoder = WebPDecoder::new(Cursor::new(&data)).unwrap();
+            decoder.set_upsampling(filter);
+            let mut rgb = vec![0; decoder.total_bytes() as usize];
+            decoder.read_image(&mut rgb).unwrap();
+            for (i, pixel) in rgb.chunks(3).enumerate() {
+                assert_eq!(pixel, yuv::yuv_to_rgb(y444[i], u444[i], v444[i]));
+            }
+        }
+
+        let decoder = WebPDecoder::new(Cursor::new(animation())).unwrap();
+        match decoder.into_yuv420() {
+            Err(ImageError::Unsupported(_)) => {}
+            _ => panic!("expected YUV output of animations to be unsupported"),
+        }
+    }
+
+    #[test]
+    fn read_image_planar() {
+        let data = riff(&[vp8()]);
+
+        let decoder = WebPDecoder::new(Cursor::new(&data)).unwrap();
+        let mut rgb = vec![0; decoder.total_bytes() as usize];
+        decoder.read_image(&mut rgb).unwrap();
+
+        let decoder = WebPDecoder::new(Cursor::new(&data)).unwrap();
+        let (red, green, blue) = decoder.read_image_planar().unwrap();
+        assert_eq!((red.len(), green.len(), blue.len()), (100 * 100, 100 * 100, 100 * 100));
+
+        for (i, pixel) in rgb.chunks(3).enumerate() {
+            assert_eq!(pixel, [red[i], green[i], blue[i]]);
+        }
+    }
+
+    #[test]
+    fn dimensions_of_corrupt_frame() {
+        let mut data = riff(&[vp8()]);
+        // Truncate the first partition by shrinking the image data after its header.
+        data.truncate(20 + 40);
+        let len = data.len() as u32;
+        data[16..20].copy_from_slice(&(len - 20).to_le_bytes());
+        data[4..8].copy_from_slice(&(len - 8).to_le_bytes());
+
+        let err = match WebPDecoder::new(Cursor::new(&data)) {
+            Err(ImageError::Decoding(err)) => err,
+            _ => panic!("expected a decoding error"),
+        };
+
+        let frame_error = err.source().and_then(|err| err.downcast_ref::<FrameError>()).unwrap();
+        assert_eq!(frame_error.dimensions(), (100, 100));
+    }
+
+    #[test]
+    fn read_image_f32() {
+        let data = riff(&[vp8()]);
+
+        let decoder = WebPDecoder::new(Cursor::new(&data)).unwrap();
+        let mut narrow = vec![0; decoder.total_bytes() as usize];
+        decoder.read_image(&mut narrow).unwrap();
+
+        let decoder = WebPDecoder::new(Cursor::new(&data)).unwrap();
+        let mut scaled = vec![0.0; decoder.total_bytes() as usize];
+        decoder.read_image_f32(&mut scaled).unwrap();
+
+        let mean = [0.485, 0.456, 0.406];
+        let std = [0.229, 0.224, 0.225];
+        let mut decoder = WebPDecoder::new(Cursor::new(&data)).unwrap();
+        decoder.set_normalization(mean, std);
+        let mut normalized = vec![0.0; decoder.total_bytes() as usize];
+        decoder.read_image_f32(&mut normalized).unwrap();
+
+        for (i, &sample) in narrow.iter().enumerate() {
+            let value = f32::from(sample) / 255.0;
+            assert_eq!(scaled[i], value);
+            assert_eq!(normalized[i], (value - mean[i % 3]) / std[i % 3]);
+        }
+    }
+
+    #[test]
+    fn decode_from_memory() {
+        let data = riff(&[vp8()]);
+
+        let decoder = WebPDecoder::new(Cursor::new(&data)).unwrap();
+        let mut expected = vec![0; decoder.total_bytes() as usize];
+        decoder.read_image(&mut expected).unwrap();
+
+        match decode(&data).unwrap() {
+            DynamicImage::ImageRgb8(image) => assert_eq!(image.into_raw(), expected),
+            _ => panic!("expected an RGB image"),
+        }
+
+        assert!(decode(b"GIF89a").is_err());
+    }
 }
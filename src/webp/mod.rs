@@ -1,8 +1,17 @@
 //! Decoding of WebP Images
 
-pub use self::decoder::WebPDecoder;
+pub use self::decoder::{
+    decode, sniff, DecodeWarning, Features, FrameError, Limits, PremultiplyRounding, TileCoord,
+    WebPDecoder,
+};
+#[cfg(feature = "png")]
+pub use self::decoder::transcode_to_png;
+pub use self::yuv::UpsampleFilter;
 
+mod alpha;
+mod animation;
 mod decoder;
 mod transform;
+mod yuv;
 
 pub mod vp8;
@@ -3,7 +3,7 @@
 //! This module contains a partial implementation of the
 //! VP8 video format as defined in RFC-6386.
 //!
-//! It decodes Keyframes only sans Loop Filtering.
+//! It decodes Keyframes only.
 //! VP8 is the underpinning of the WebP image format
 //!
 //! # Related Links
@@ -16,8 +16,10 @@ use byteorder::{LittleEndian, ReadBytesExt};
 use std::default::Default;
 use std::cmp;
 use std::io::Read;
+use std::mem;
 
 use super::transform;
+use super::yuv::{self, UpsampleFilter};
 use crate::error::{
     DecodingError, ImageError, ImageResult, UnsupportedError, UnsupportedErrorKind,
 };
@@ -672,6 +674,8 @@ struct BoolReader {
     range: u32,
     value: u32,
     bit_count: u8,
+    /// Whether bits past the end of the buffer were needed
+    exhausted: bool,
 }
 
 impl BoolReader {
@@ -682,6 +686,7 @@ impl BoolReader {
             value: 0,
             bit_count: 0,
             index: 0,
+            exhausted: false,
         }
     }
 
@@ -699,6 +704,7 @@ impl BoolReader {
         self.index = 2;
         self.range = 255;
         self.bit_count = 0;
+        self.exhausted = false;
 
         Ok(())
     }
@@ -729,6 +735,8 @@ impl BoolReader {
                 if self.index < self.buf.len() {
                     self.value |= u32::from(self.buf[self.index]);
                     self.index += 1;
+                } else {
+                    self.exhausted = true;
                 }
             }
         }
@@ -736,6 +744,14 @@ impl BoolReader {
         retval
     }
 
+    /// Returns whether the buffer ran out of bytes, after which zero bits are read.
+    ///
+    /// The bytes are loaded ahead of the bits that are decoded, so this may be set for the last
+    /// few bits of a complete buffer.
+    pub(crate) fn is_exhausted(&self) -> bool {
+        self.exhausted
+    }
+
     pub(crate) fn read_literal(&mut self, n: u8) -> u8 {
         let mut v = 0u8;
         let mut n = n;
@@ -789,6 +805,45 @@ struct MacroBlock {
     segmentid: u8,
 }
 
+/// The loop filter parameters of a macroblock, see Section 15.1
+#[derive(Default, Clone, Copy)]
+struct FilterParameters {
+    /// The filter level, the macroblock is not filtered when it is zero
+    level: u8,
+    interior_limit: u8,
+    hev_threshold: u8,
+    /// Whether the edges between the subblocks are filtered
+    inner: bool,
+}
+
+/// The updates of the reference frames and probabilities signalled by a frame header, as defined
+/// by Sections 9.7 to 9.9 of the VP8 Specification
+///
+/// Keyframes refresh all reference frames and reset the sign biases, so only the entropy flag is
+/// stored in their header. The others are parsed from the headers of inter frames, which are not
+/// otherwise decoded.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReferenceUpdates {
+    /// Whether the golden frame is replaced by this frame
+    pub refresh_golden_frame: bool,
+    /// Whether the alternate reference frame is replaced by this frame
+    pub refresh_alternate_frame: bool,
+    /// The buffer copied to the golden frame before decoding unless it is refreshed: none if 0,
+    /// the last frame if 1 and the alternate reference frame if 2
+    pub copy_buffer_to_golden: u8,
+    /// The buffer copied to the alternate reference frame before decoding unless it is refreshed:
+    /// none if 0, the last frame if 1 and the golden frame if 2
+    pub copy_buffer_to_alternate: u8,
+    /// Whether the motion vectors referencing the golden frame are inverted
+    pub sign_bias_golden: bool,
+    /// Whether the motion vectors referencing the alternate reference frame are inverted
+    pub sign_bias_alternate: bool,
+    /// Whether the probabilities updated by this frame are kept for the following frames
+    pub refresh_entropy_probs: bool,
+    /// Whether the last frame is replaced by this frame
+    pub refresh_last: bool,
+}
+
 /// A Representation of the last decoded video frame
 #[derive(Default, Debug, Clone)]
 pub struct Frame {
@@ -801,14 +856,25 @@ pub struct Frame {
     /// The luma plane of the frame
     pub ybuf: Vec<u8>,
 
+    /// The blue-difference chroma plane of the frame, subsampled by two in both directions
+    pub(crate) ubuf: Vec<u8>,
+
+    /// The red-difference chroma plane of the frame, subsampled by two in both directions
+    pub(crate) vbuf: Vec<u8>,
+
     /// Indicates whether this frame is a keyframe
     pub keyframe: bool,
 
-    version: u8,
+    /// The version of the bitstream, selecting the reconstruction and loop filters as defined by
+    /// Section 9.1 of the VP8 Specification
+    pub version: u8,
 
     /// Indicates whether this frame is intended for display
     pub for_display: bool,
 
+    /// The updates of the reference frames signalled by the frame header
+    pub references: ReferenceUpdates,
+
     // Section 9.2
     /// The pixel type of the frame as defined by Section 9.2
     /// of the VP8 Specification
@@ -820,6 +886,135 @@ pub struct Frame {
     sharpness_level: u8,
 }
 
+impl Frame {
+    /// The width of the chroma planes
+    fn chroma_width(&self) -> u16 {
+        (self.width + 1) / 2
+    }
+
+    /// The height of the chroma planes
+    fn chroma_height(&self) -> u16 {
+        (self.height + 1) / 2
+    }
+
+    /// Returns the luma plane as `(samples, width, height, stride)`.
+    ///
+    /// Samples are stored row-major with one byte per sample, so the byte order of the platform
+    /// does not matter. Row `y` starts at index `y * stride`.
+    pub fn y_plane(&self) -> (&[u8], u16, u16, usize) {
+        (&self.ybuf, self.width, self.height, usize::from(self.width))
+    }
+
+    /// Returns the blue-difference chroma plane as `(samples, width, height, stride)`.
+    ///
+    /// The chroma planes are subsampled by two in both directions, rounding up, so that each
+    /// sample covers a 2x2 block of luma samples. The layout is the same as for `y_plane`.
+    pub fn u_plane(&self) -> (&[u8], u16, u16, usize) {
+        let width = self.chroma_width();
+        (&self.ubuf, width, self.chroma_height(), usize::from(width))
+    }
+
+    /// Returns the red-difference chroma plane as `(samples, width, height, stride)`.
+    ///
+    /// See `u_plane` for the layout.
+    pub fn v_plane(&self) -> (&[u8], u16, u16, usize) {
+        let width = self.chroma_width();
+        (&self.vbuf, width, self.chroma_height(), usize::from(width))
+    }
+
+    /// Checks that the planes have the sizes given by the dimensions, as the conversion to RGB
+    /// relies on it.
+    pub(crate) fn check_planes(&self) -> ImageResult<()> {
+        let luma = usize::from(self.width) * usize::from(self.height);
+        let chroma = usize::from(self.chroma_width()) * usize::from(self.chroma_height());
+        if self.ybuf.len() != luma || self.ubuf.len() != chroma || self.vbuf.len() != chroma {
+            return Err(ImageError::Decoding(DecodingError::with_message(
+                ImageFormat::WebP.into(),
+                format!(
+                    "Planes of {}, {} and {} samples in a frame of {}x{} pixels",
+                    self.ybuf.len(),
+                    self.ubuf.len(),
+                    self.vbuf.len(),
+                    self.width,
+                    self.height,
+                ),
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Fills the pixels from row `y` down with gray, for frames whose data ends before them.
+    fn fill_from_row(&mut self, y: usize) {
+        fn fill(plane: &mut [u8], start: usize) {
+            let start = cmp::min(start, plane.len());
+            for sample in &mut plane[start..] {
+                *sample = 128;
+            }
+        }
+
+        // The chroma samples covering the last intact row are kept.
+        let chroma = (y / 2 + y % 2) * usize::from(self.chroma_width());
+        fill(&mut self.ybuf, y * usize::from(self.width));
+        fill(&mut self.ubuf, chroma);
+        fill(&mut self.vbuf, chroma);
+    }
+
+    /// Panics in debug builds if the planes are inconsistent with the dimensions.
+    ///
+    /// This is called on every reconstructed frame, so that bugs of the decoder show up where
+    /// they happen instead of as corrupt images or failing conversions. Release builds rely on
+    /// `check_planes` before the conversion instead.
+    fn debug_check_planes(&self) {
+        if cfg!(debug_assertions) {
+            if let Err(err) = self.check_planes() {
+                panic!("Reconstruction produced inconsistent planes: {}", err);
+            }
+        }
+    }
+
+    /// Converts the frame to RGB, writing three bytes per pixel into `buf`.
+    ///
+    /// Each chroma sample is used for all four pixels of the 2x2 block it covers. At odd
+    /// dimensions the last column and row of chroma samples only cover a single column or row of
+    /// pixels, which replicates them to the edge without reading past the chroma planes.
+    pub fn fill_rgb(&self, buf: &mut [u8]) {
+        let width = usize::from(self.width);
+        assert_eq!(buf.len(), 3 * width * usize::from(self.height));
+
+        for (y, row) in buf.chunks_mut(3 * width).enumerate() {
+            self.fill_rgb_row(y, row, UpsampleFilter::Point);
+        }
+    }
+
+    /// Converts row `y` of the frame to RGB, writing three bytes per pixel into `row`.
+    pub(crate) fn fill_rgb_row(&self, y: usize, row: &mut [u8], filter: UpsampleFilter) {
+        let width = usize::from(self.width);
+        let chroma_width = usize::from(self.chroma_width());
+        let luma = &self.ybuf[y * width..][..width];
+
+        if filter == UpsampleFilter::Point {
+            let chroma = (y / 2) * chroma_width;
+            yuv::yuv_to_rgb_row(
+                luma,
+                &self.ubuf[chroma..][..chroma_width],
+                &self.vbuf[chroma..][..chroma_width],
+                row,
+            );
+            return;
+        }
+
+        let chroma_height = usize::from(self.chroma_height());
+        let (mut u, mut v) = (vec![0; width], vec![0; width]);
+        yuv::upsample_row(filter, &self.ubuf, chroma_width, chroma_height, y, &mut u);
+        yuv::upsample_row(filter, &self.vbuf, chroma_width, chroma_height, y, &mut v);
+
+        for (((pixel, &y), &u), &v) in row.chunks_mut(3).zip(luma).zip(&u).zip(&v) {
+            pixel.copy_from_slice(&yuv::yuv_to_rgb(y, u, v));
+        }
+    }
+}
+
 #[derive(Clone, Copy, Default)]
 struct Segment {
     ydc: i16,
@@ -837,6 +1032,44 @@ struct Segment {
     loopfilter_level: i8,
 }
 
+impl Segment {
+    /// Sets the dequantization factors of the segment, Section 9.6 and 14.1
+    ///
+    /// `index` is the quantizer index of the segment, `deltas` are the deltas of the frame header
+    /// for the luma dc, y2 dc, y2 ac, chroma dc and chroma ac coefficients. Both the index and
+    /// the index for each coefficient type are clamped to the range of the lookup tables.
+    fn set_quantizers(&mut self, index: i32, deltas: [i32; 5]) {
+        fn dc_quant(index: i32) -> i16 {
+            DC_QUANT[clamp(index, 0, 127) as usize]
+        }
+
+        fn ac_quant(index: i32) -> i16 {
+            AC_QUANT[clamp(index, 0, 127) as usize]
+        }
+
+        let [ydc_delta, y2dc_delta, y2ac_delta, uvdc_delta, uvac_delta] = deltas;
+        let base = clamp(index, 0, 127);
+
+        self.ydc = dc_quant(base + ydc_delta);
+        self.yac = ac_quant(base);
+
+        self.y2dc = dc_quant(base + y2dc_delta) * 2;
+        // The intermediate result (max`284*155`) can be larger than the `i16` range.
+        self.y2ac = (i32::from(ac_quant(base + y2ac_delta)) * 155 / 100) as i16;
+
+        self.uvdc = dc_quant(base + uvdc_delta);
+        self.uvac = ac_quant(base + uvac_delta);
+
+        if self.y2ac < 8 {
+            self.y2ac = 8;
+        }
+
+        if self.uvdc > 132 {
+            self.uvdc = 132;
+        }
+    }
+}
+
 /// VP8 Decoder
 ///
 /// Only decodes keyframes
@@ -853,6 +1086,11 @@ pub struct Vp8Decoder<R> {
     segments_update_map: bool,
     segment: [Segment; MAX_SEGMENTS],
 
+    // Section 9.6
+    loop_filter_adjustments: bool,
+    ref_delta: [i32; 4],
+    mode_delta: [i32; 4],
+
     partitions: [BoolReader; 8],
     num_partitions: u8,
 
@@ -870,6 +1108,33 @@ pub struct Vp8Decoder<R> {
 
     top_border: Vec<u8>,
     left_border: Vec<u8>,
+
+    top_border_u: Vec<u8>,
+    left_border_u: Vec<u8>,
+
+    top_border_v: Vec<u8>,
+    left_border_v: Vec<u8>,
+
+    // The reconstructed planes padded to whole macroblocks, as the loop filter also uses the
+    // pixels beyond the border of the frame.
+    luma: Vec<u8>,
+    chroma_u: Vec<u8>,
+    chroma_v: Vec<u8>,
+
+    filters: Vec<FilterParameters>,
+
+    progress: Option<Box<dyn FnMut(f32) + Send>>,
+
+    /// Whether the rows before invalid or missing macroblock data are kept
+    partial: bool,
+    /// The expected length of the frame data
+    data_len: Option<u32>,
+    /// Whether the frame data ends before `data_len`
+    truncated: bool,
+    /// The rows of pixels of the last frame reconstructed before its data ran out or it failed
+    intact_rows: u16,
+    /// Whether the last frame was cut short
+    salvaged: bool,
 }
 
 impl<R: Read> Vp8Decoder<R> {
@@ -892,6 +1157,10 @@ impl<R: Read> Vp8Decoder<R> {
             segments_update_map: false,
             segment: [s; MAX_SEGMENTS],
 
+            loop_filter_adjustments: false,
+            ref_delta: [0; 4],
+            mode_delta: [0; 4],
+
             partitions: [
                 BoolReader::new(),
                 BoolReader::new(),
@@ -919,9 +1188,38 @@ impl<R: Read> Vp8Decoder<R> {
 
             top_border: Vec::new(),
             left_border: Vec::new(),
+
+            top_border_u: Vec::new(),
+            left_border_u: Vec::new(),
+
+            top_border_v: Vec::new(),
+            left_border_v: Vec::new(),
+
+            luma: Vec::new(),
+            chroma_u: Vec::new(),
+            chroma_v: Vec::new(),
+
+            filters: Vec::new(),
+
+            progress: None,
+
+            partial: false,
+            data_len: None,
+            truncated: false,
+            intact_rows: 0,
+            salvaged: false,
         }
     }
 
+    /// Sets a callback reporting the progress of the reconstruction.
+    ///
+    /// The callback is invoked on the decoding thread after each row of macroblocks, with the
+    /// fraction of rows that have been reconstructed. A value of `1.0` is reported before the
+    /// loop filter is applied to the whole frame.
+    pub fn set_progress_callback(&mut self, callback: impl FnMut(f32) + Send + 'static) {
+        self.progress = Some(Box::new(callback));
+    }
+
     fn update_token_probabilities(&mut self) {
         for (i, is) in COEFF_UPDATE_PROBS.iter().enumerate() {
             for (j, js) in is.iter().enumerate() {
@@ -955,50 +1253,30 @@ impl<R: Read> Vp8Decoder<R> {
 
         let mut buf = Vec::new();
         self.r.read_to_end(&mut buf)?;
+
+        // The frame tag, the keyframe header, the first partition, the sizes of the others and
+        // all partitions have been read.
+        let header = if self.frame.keyframe { 10 } else { 3 };
+        let read: usize = header + self.b.buf.len() + 3 * (n - 1)
+            + self.partitions[..n - 1].iter().map(|p| p.buf.len()).sum::<usize>()
+            + buf.len();
+        self.truncated = match self.data_len {
+            Some(len) => (read as u64) < u64::from(len),
+            None => false,
+        };
         self.partitions[n - 1].init(buf)?;
 
         Ok(())
     }
 
     fn read_quantization_indices(&mut self) {
-        fn dc_quant(index: i32) -> i16 {
-            DC_QUANT[clamp(index, 0, 127) as usize]
-        }
-
-        fn ac_quant(index: i32) -> i16 {
-            AC_QUANT[clamp(index, 0, 127) as usize]
-        }
-
         let yac_abs = self.b.read_literal(7);
-        let ydc_delta = if self.b.read_flag() {
-            self.b.read_magnitude_and_sign(4)
-        } else {
-            0
-        };
-
-        let y2dc_delta = if self.b.read_flag() {
-            self.b.read_magnitude_and_sign(4)
-        } else {
-            0
-        };
-
-        let y2ac_delta = if self.b.read_flag() {
-            self.b.read_magnitude_and_sign(4)
-        } else {
-            0
-        };
-
-        let uvdc_delta = if self.b.read_flag() {
-            self.b.read_magnitude_and_sign(4)
-        } else {
-            0
-        };
-
-        let uvac_delta = if self.b.read_flag() {
-            self.b.read_magnitude_and_sign(4)
-        } else {
-            0
-        };
+        let mut deltas = [0i32; 5];
+        for delta in &mut deltas {
+            if self.b.read_flag() {
+                *delta = self.b.read_magnitude_and_sign(4);
+            }
+        }
 
         let n = if self.segments_enabled {
             MAX_SEGMENTS
@@ -1006,52 +1284,80 @@ impl<R: Read> Vp8Decoder<R> {
             1
         };
         for i in 0usize..n {
-            let base = i32::from(if !self.segment[i].delta_values {
-                i16::from(self.segment[i].quantizer_level)
+            let base = if !self.segments_enabled {
+                i32::from(yac_abs)
+            } else if !self.segment[i].delta_values {
+                i32::from(self.segment[i].quantizer_level)
             } else {
-                i16::from(self.segment[i].quantizer_level) + i16::from(yac_abs)
-            });
-
-            self.segment[i].ydc = dc_quant(base + ydc_delta);
-            self.segment[i].yac = ac_quant(base);
+                i32::from(self.segment[i].quantizer_level) + i32::from(yac_abs)
+            };
 
-            self.segment[i].y2dc = dc_quant(base + y2dc_delta) * 2;
-            // The intermediate result (max`284*155`) can be larger than the `i16` range.
-            self.segment[i].y2ac = (i32::from(ac_quant(base + y2ac_delta)) * 155 / 100) as i16;
+            self.segment[i].set_quantizers(base, deltas);
+        }
+    }
 
-            self.segment[i].uvdc = dc_quant(base + uvdc_delta);
-            self.segment[i].uvac = ac_quant(base + uvac_delta);
+    /// Reads the reference frame updates and the entropy flag, Section 9.7 to 9.9
+    fn read_reference_updates(&mut self) -> ImageResult<()> {
+        if self.frame.keyframe {
+            self.frame.references = ReferenceUpdates {
+                refresh_golden_frame: true,
+                refresh_alternate_frame: true,
+                copy_buffer_to_golden: 0,
+                copy_buffer_to_alternate: 0,
+                sign_bias_golden: false,
+                sign_bias_alternate: false,
+                refresh_entropy_probs: self.b.read_flag(),
+                refresh_last: true,
+            };
+            return Ok(());
+        }
 
-            if self.segment[i].y2ac < 8 {
-                self.segment[i].y2ac = 8;
-            }
+        let refresh_golden_frame = self.b.read_flag();
+        let refresh_alternate_frame = self.b.read_flag();
+        let copy_buffer_to_golden = if refresh_golden_frame { 0 } else { self.b.read_literal(2) };
+        let copy_buffer_to_alternate = if refresh_alternate_frame {
+            0
+        } else {
+            self.b.read_literal(2)
+        };
 
-            if self.segment[i].uvdc > 132 {
-                self.segment[i].uvdc = 132;
-            }
+        if copy_buffer_to_golden == 3 || copy_buffer_to_alternate == 3 {
+            return Err(ImageError::Decoding(DecodingError::with_message(
+                ImageFormat::WebP.into(),
+                "Invalid buffer copied to a VP8 reference frame".to_string(),
+            )));
         }
+
+        self.frame.references = ReferenceUpdates {
+            refresh_golden_frame,
+            refresh_alternate_frame,
+            copy_buffer_to_golden,
+            copy_buffer_to_alternate,
+            sign_bias_golden: self.b.read_flag(),
+            sign_bias_alternate: self.b.read_flag(),
+            refresh_entropy_probs: self.b.read_flag(),
+            refresh_last: self.b.read_flag(),
+        };
+
+        Ok(())
     }
 
     fn read_loop_filter_adjustments(&mut self) {
         if self.b.read_flag() {
-            for _i in 0usize..4 {
+            for i in 0usize..4 {
                 let ref_frame_delta_update_flag = self.b.read_flag();
 
-                let _delta = if ref_frame_delta_update_flag {
-                    self.b.read_magnitude_and_sign(6)
-                } else {
-                    0i32
-                };
+                if ref_frame_delta_update_flag {
+                    self.ref_delta[i] = self.b.read_magnitude_and_sign(6);
+                }
             }
 
-            for _i in 0usize..4 {
+            for i in 0usize..4 {
                 let mb_mode_delta_update_flag = self.b.read_flag();
 
-                let _delta = if mb_mode_delta_update_flag {
-                    self.b.read_magnitude_and_sign(6)
-                } else {
-                    0i32
-                };
+                if mb_mode_delta_update_flag {
+                    self.mode_delta[i] = self.b.read_magnitude_and_sign(6);
+                }
             }
         }
     }
@@ -1105,6 +1411,13 @@ impl<R: Read> Vp8Decoder<R> {
         self.frame.version = ((tag >> 1) & 7) as u8;
         self.frame.for_display = (tag >> 4) & 1 != 0;
 
+        if self.frame.version > 3 {
+            return Err(ImageError::Decoding(DecodingError::with_message(
+                ImageFormat::WebP.into(),
+                format!("Unknown VP8 version {}", self.frame.version),
+            )));
+        }
+
         let first_partition_size = tag >> 5;
 
         if self.frame.keyframe {
@@ -1114,7 +1427,7 @@ impl<R: Read> Vp8Decoder<R> {
             if tag != [0x9d, 0x01, 0x2a] {
                 return Err(ImageError::Decoding(DecodingError::with_message(
                     ImageFormat::WebP.into(),
-                    format!("Invalid magic bytes {:?} for vp8", tag),
+                    format!("Invalid VP8 keyframe start code {:02x?}", tag),
                 )));
             }
 
@@ -1132,10 +1445,27 @@ impl<R: Read> Vp8Decoder<R> {
             self.mbwidth = (self.frame.width + 15) / 16;
             self.mbheight = (self.frame.height + 15) / 16;
 
-            self.frame.ybuf = vec![0u8; self.frame.width as usize * self.frame.height as usize];
+            // The planes keep their allocation when a frame is decoded into an earlier one.
+            let luma_size = self.frame.width as usize * self.frame.height as usize;
+            let chroma_size = self.frame.chroma_width() as usize * self.frame.chroma_height() as usize;
+            resize_plane(&mut self.frame.ybuf, luma_size);
+            resize_plane(&mut self.frame.ubuf, chroma_size);
+            resize_plane(&mut self.frame.vbuf, chroma_size);
 
             self.top_border = vec![127u8; self.frame.width as usize + 4 + 16];
             self.left_border = vec![129u8; 1 + 16];
+
+            self.top_border_u = vec![127u8; 8 * self.mbwidth as usize];
+            self.left_border_u = vec![129u8; 1 + 8];
+
+            self.top_border_v = vec![127u8; 8 * self.mbwidth as usize];
+            self.left_border_v = vec![129u8; 1 + 8];
+
+            let mbcount = self.mbwidth as usize * self.mbheight as usize;
+            self.luma = vec![0u8; 256 * mbcount];
+            self.chroma_u = vec![0u8; 64 * mbcount];
+            self.chroma_v = vec![0u8; 64 * mbcount];
+            self.filters = Vec::with_capacity(mbcount);
         }
 
         let mut buf = vec![0; first_partition_size as usize];
@@ -1165,8 +1495,8 @@ impl<R: Read> Vp8Decoder<R> {
         self.frame.filter_level = self.b.read_literal(6);
         self.frame.sharpness_level = self.b.read_literal(3);
 
-        let lf_adjust_enable = self.b.read_flag();
-        if lf_adjust_enable {
+        self.loop_filter_adjustments = self.b.read_flag();
+        if self.loop_filter_adjustments {
             self.read_loop_filter_adjustments();
         }
 
@@ -1176,8 +1506,8 @@ impl<R: Read> Vp8Decoder<R> {
 
         self.read_quantization_indices();
 
+        self.read_reference_updates()?;
         if !self.frame.keyframe {
-            // 9.7 refresh golden frame and altref frame
             // FIXME: support this?
             return Err(ImageError::Unsupported(
                 UnsupportedError::from_format_and_kind(
@@ -1187,9 +1517,6 @@ impl<R: Read> Vp8Decoder<R> {
                     ),
                 ),
             ));
-        } else {
-            // Refresh entropy probs ?????
-            let _ = self.b.read_literal(1);
         }
 
         self.update_token_probabilities();
@@ -1231,10 +1558,10 @@ impl<R: Read> Vp8Decoder<R> {
             0
         };
 
-        let skip_coeff = if self.prob_skip_false.is_some() {
-            self.b.read_bool(*self.prob_skip_false.as_ref().unwrap())
-        } else {
-            false
+        // Without `mb_no_skip_coeff` every macroblock carries its coefficients.
+        let skip_coeff = match self.prob_skip_false {
+            Some(prob) => self.b.read_bool(prob),
+            None => false,
         };
 
         let inter_predicted = if !self.frame.keyframe {
@@ -1317,8 +1644,8 @@ impl<R: Read> Vp8Decoder<R> {
 
     fn intra_predict(&mut self, mbx: usize, mby: usize, mb: &MacroBlock, resdata: &[i32]) {
         let stride = 1usize + 16 + 4;
-        let w = self.frame.width as usize;
         let mw = self.mbwidth as usize;
+        let w = 16 * mw;
         let mut ws = create_border(mbx, mby, mw, &self.top_border, &self.left_border);
 
         match mb.luma_mode {
@@ -1352,14 +1679,68 @@ impl<R: Read> Vp8Decoder<R> {
             self.left_border[i + 1] = ws[(i + 1) * stride + 16];
         }
 
-        // Length is the remainder to the border, but maximally the current chunk.
-        let ylength = cmp::min(self.frame.height as usize - mby*16, 16);
-        let xlength = cmp::min(self.frame.width as usize - mbx*16, 16);
+        for y in 0usize..16 {
+            let row = (mby * 16 + y) * w + mbx * 16;
+            self.luma[row..row + 16].copy_from_slice(&ws[(1 + y) * stride + 1..][..16]);
+        }
+    }
+
+    fn intra_predict_chroma(&mut self, mbx: usize, mby: usize, mb: &MacroBlock, resdata: &[i32]) {
+        let stride = 1usize + 8;
+        let w = 8 * self.mbwidth as usize;
+        let mut uws = create_border_chroma(mbx, mby, &self.top_border_u, &self.left_border_u);
+        let mut vws = create_border_chroma(mbx, mby, &self.top_border_v, &self.left_border_v);
 
-        for y in 0usize..ylength {
-            for x in 0usize..xlength {
-                self.frame.ybuf[(mby * 16 + y) * w + mbx * 16 + x] = ws[(1 + y) * stride + 1 + x];
+        match mb.chroma_mode {
+            ChromaMode::V => {
+                predict_vpred(&mut uws, 8, 1, 1, stride);
+                predict_vpred(&mut vws, 8, 1, 1, stride);
+            }
+            ChromaMode::H => {
+                predict_hpred(&mut uws, 8, 1, 1, stride);
+                predict_hpred(&mut vws, 8, 1, 1, stride);
+            }
+            ChromaMode::TM => {
+                predict_tmpred(&mut uws, 8, 1, 1, stride);
+                predict_tmpred(&mut vws, 8, 1, 1, stride);
             }
+            ChromaMode::DC => {
+                predict_dcpred(&mut uws, 8, stride, mby != 0, mbx != 0);
+                predict_dcpred(&mut vws, 8, stride, mby != 0, mbx != 0);
+            }
+        }
+
+        for y in 0usize..2 {
+            for x in 0usize..2 {
+                let i = x + y * 2;
+                let y0 = 1 + y * 4;
+                let x0 = 1 + x * 4;
+
+                // The residue of the u blocks starts at block 16, the one of the v blocks at 20.
+                let mut rb = [0i32; 16];
+                rb.copy_from_slice(&resdata[(16 + i) * 16..(16 + i) * 16 + 16]);
+                add_residue(&mut uws, &rb, y0, x0, stride);
+
+                rb.copy_from_slice(&resdata[(20 + i) * 16..(20 + i) * 16 + 16]);
+                add_residue(&mut vws, &rb, y0, x0, stride);
+            }
+        }
+
+        self.left_border_u[0] = uws[8];
+        self.left_border_v[0] = vws[8];
+
+        for i in 0usize..8 {
+            self.top_border_u[mbx * 8 + i] = uws[8 * stride + 1 + i];
+            self.left_border_u[i + 1] = uws[(i + 1) * stride + 8];
+
+            self.top_border_v[mbx * 8 + i] = vws[8 * stride + 1 + i];
+            self.left_border_v[i + 1] = vws[(i + 1) * stride + 8];
+        }
+
+        for y in 0usize..8 {
+            let row = (mby * 8 + y) * w + mbx * 8;
+            self.chroma_u[row..row + 8].copy_from_slice(&uws[(1 + y) * stride + 1..][..8]);
+            self.chroma_v[row..row + 8].copy_from_slice(&vws[(1 + y) * stride + 1..][..8]);
         }
     }
 
@@ -1441,9 +1822,11 @@ impl<R: Read> Vp8Decoder<R> {
         has_coefficients
     }
 
-    fn read_residual_data(&mut self, mb: &MacroBlock, mbx: usize, p: usize) -> [i32; 384] {
+    /// Returns the residue of the macroblock and whether any of its blocks has coefficients.
+    fn read_residual_data(&mut self, mb: &MacroBlock, mbx: usize, p: usize) -> ([i32; 384], bool) {
         let sindex = mb.segmentid as usize;
         let mut blocks = [0i32; 384];
+        let mut has_coefficients = false;
         let mut plane = if mb.luma_mode == LumaMode::B { 3 } else { 1 };
 
         if plane == 1 {
@@ -1452,6 +1835,7 @@ impl<R: Read> Vp8Decoder<R> {
             let dcq = self.segment[sindex].y2dc;
             let acq = self.segment[sindex].y2ac;
             let n = self.read_coefficients(&mut block, p, plane, complexity as usize, dcq, acq);
+            has_coefficients |= n;
 
             self.left.complexity[0] = if n { 1 } else { 0 };
             self.top[mbx].complexity[0] = if n { 1 } else { 0 };
@@ -1476,6 +1860,7 @@ impl<R: Read> Vp8Decoder<R> {
                 let acq = self.segment[sindex].yac;
 
                 let n = self.read_coefficients(block, p, plane, complexity as usize, dcq, acq);
+                has_coefficients |= n;
 
                 if block[0] != 0 || n {
                     transform::idct4x4(block);
@@ -1503,6 +1888,8 @@ impl<R: Read> Vp8Decoder<R> {
                     let acq = self.segment[sindex].uvac;
 
                     let n = self.read_coefficients(block, p, plane, complexity as usize, dcq, acq);
+                    has_coefficients |= n;
+
                     if block[0] != 0 || n {
                         transform::idct4x4(block);
                     }
@@ -1515,20 +1902,170 @@ impl<R: Read> Vp8Decoder<R> {
             }
         }
 
-        blocks
+        (blocks, has_coefficients)
     }
 
-    /// Decodes the current frame and returns a reference to it
-    pub fn decode_frame(&mut self) -> ImageResult<&Frame> {
-        self.read_frame_header()?;
+    /// Whether the frame is loop filtered, see Section 9.1 and 9.6
+    fn loop_filter_enabled(&self) -> bool {
+        // Versions 2 and 3 are reconstructed without the loop filter.
+        self.frame.version < 2 && self.frame.filter_level != 0
+    }
+
+    fn filter_parameters(&self, mb: &MacroBlock, has_coefficients: bool) -> FilterParameters {
+        let segment = &self.segment[mb.segmentid as usize];
+        let mut level = i32::from(self.frame.filter_level);
+
+        if self.segments_enabled {
+            level = i32::from(segment.loopfilter_level) + if segment.delta_values { level } else { 0 };
+            level = clamp(level, 0, 63);
+        }
+
+        if self.loop_filter_adjustments {
+            // Only the deltas of intra frames and of the `B_PRED` mode apply to keyframes.
+            level += self.ref_delta[0];
+            if mb.luma_mode == LumaMode::B {
+                level += self.mode_delta[0];
+            }
+            level = clamp(level, 0, 63);
+        }
+
+        let sharpness = self.frame.sharpness_level;
+        let mut interior_limit = level as u8;
+        if sharpness != 0 {
+            interior_limit >>= if sharpness > 4 { 2 } else { 1 };
+            interior_limit = cmp::min(interior_limit, 9 - sharpness);
+        }
+
+        let hev_threshold = if level >= 40 {
+            2
+        } else if level >= 15 {
+            1
+        } else {
+            0
+        };
+
+        FilterParameters {
+            level: level as u8,
+            interior_limit: cmp::max(interior_limit, 1),
+            hev_threshold,
+            inner: mb.luma_mode == LumaMode::B || has_coefficients,
+        }
+    }
+
+    /// Applies the loop filter to the reconstructed frame, Section 15
+    fn loop_filter(&mut self) {
+        let mbwidth = self.mbwidth as usize;
+        let simple = self.frame.filter == 1;
 
         for mby in 0..self.mbheight as usize {
+            for mbx in 0..mbwidth {
+                let params = self.filters[mby * mbwidth + mbx];
+                if params.level == 0 {
+                    continue;
+                }
+
+                filter_macroblock(&mut self.luma, 16 * mbwidth, 16, mbx, mby, params, simple);
+
+                // The simple filter only applies to the luma plane.
+                if !simple {
+                    filter_macroblock(&mut self.chroma_u, 8 * mbwidth, 8, mbx, mby, params, false);
+                    filter_macroblock(&mut self.chroma_v, 8 * mbwidth, 8, mbx, mby, params, false);
+                }
+            }
+        }
+    }
+
+    /// Copies the visible part of the reconstructed planes into the frame.
+    fn crop_planes(&mut self) {
+        fn crop(plane: &[u8], stride: usize, dst: &mut [u8], width: usize) {
+            for (dst, src) in dst.chunks_mut(width).zip(plane.chunks(stride)) {
+                dst.copy_from_slice(&src[..width]);
+            }
+        }
+
+        let mbwidth = self.mbwidth as usize;
+        let width = self.frame.width as usize;
+        let chroma_width = self.frame.chroma_width() as usize;
+
+        crop(&self.luma, 16 * mbwidth, &mut self.frame.ybuf, width);
+        crop(&self.chroma_u, 8 * mbwidth, &mut self.frame.ubuf, chroma_width);
+        crop(&self.chroma_v, 8 * mbwidth, &mut self.frame.vbuf, chroma_width);
+    }
+
+    /// Decodes the current frame and returns it
+    pub fn decode_frame(&mut self) -> ImageResult<Frame> {
+        let mut frame = Frame::default();
+        self.decode_frame_into(&mut frame)?;
+        Ok(frame)
+    }
+
+    /// Sets the length of the frame data, such as the size of the `VP8 ` chunk, so that
+    /// truncated data is detected. By default the data extends to the end of the reader.
+    ///
+    /// Missing data at the end of the last partition is decoded as zero bits, and reconstructs
+    /// rows that do not show the image.
+    pub fn set_data_len(&mut self, len: u32) {
+        self.data_len = Some(len);
+    }
+
+    /// Sets whether a frame whose macroblock data is invalid or truncated partway through is
+    /// returned with the rows before it, instead of an error or rows decoded from missing data.
+    /// Disabled by default.
+    ///
+    /// The macroblock rows from the one that failed to decode or whose data ran out are filled
+    /// with gray, see `is_partial` and `intact_rows`. Truncation is only detected with
+    /// `set_data_len`. Unsupported features are still an error.
+    pub fn set_partial(&mut self, partial: bool) {
+        self.partial = partial;
+    }
+
+    /// Returns whether the last frame was cut short and filled with gray, with `set_partial`.
+    pub fn is_partial(&self) -> bool {
+        self.salvaged
+    }
+
+    /// Returns how many rows of pixels at the top of the last frame were reconstructed before
+    /// its data ran out or, with `set_partial`, failed to decode.
+    ///
+    /// This is the height of the frame if nothing is missing. As the data is read slightly ahead
+    /// of the bits that are decoded, this is rounded down to whole rows of macroblocks. The loop
+    /// filter is not applied across the edge to the missing rows, so up to three rows above it
+    /// differ from the complete frame.
+    pub fn intact_rows(&self) -> u16 {
+        self.intact_rows
+    }
+
+    /// Decodes the current frame into `frame`, reusing the allocation of its planes
+    ///
+    /// The contents of `frame` are unspecified if decoding fails.
+    pub fn decode_frame_into(&mut self, frame: &mut Frame) -> ImageResult<()> {
+        mem::swap(&mut self.frame, frame);
+        let result = self.decode();
+        mem::swap(&mut self.frame, frame);
+        result
+    }
+
+    fn decode(&mut self) -> ImageResult<()> {
+        self.salvaged = false;
+        self.truncated = false;
+        self.read_frame_header()?;
+
+        // The first rows of macroblocks that failed to decode and whose data ran out
+        let (mut failed, mut exhausted) = (None, None);
+        'rows: for mby in 0..self.mbheight as usize {
             let p = mby % self.num_partitions as usize;
             self.left = MacroBlock::default();
 
             for mbx in 0..self.mbwidth as usize {
-                let (skip, mb) = self.read_macroblock_header(mbx)?;
-                let blocks = if !skip {
+                let (skip, mb) = match self.read_macroblock_header(mbx) {
+                    Ok(header) => header,
+                    Err(ImageError::Decoding(_)) if self.partial => {
+                        failed = Some(mby);
+                        break 'rows;
+                    }
+                    Err(err) => return Err(err),
+                };
+                let (blocks, has_coefficients) = if !skip {
                     self.read_residual_data(&mb, mbx, p)
                 } else {
                     if mb.luma_mode != LumaMode::B {
@@ -1541,19 +2078,69 @@ impl<R: Read> Vp8Decoder<R> {
                         self.top[mbx].complexity[i] = 0;
                     }
 
-                    [0i32; 384]
+                    ([0i32; 384], false)
                 };
 
                 self.intra_predict(mbx, mby, &mb, &blocks);
+                self.intra_predict_chroma(mbx, mby, &mb, &blocks);
+
+                let params = self.filter_parameters(&mb, has_coefficients);
+                self.filters.push(params);
             }
 
             self.left_border = vec![129u8; 1 + 16];
+            self.left_border_u = vec![129u8; 1 + 8];
+            self.left_border_v = vec![129u8; 1 + 8];
+
+            let ran_out = self.b.is_exhausted() || self.partitions[p].is_exhausted();
+            if self.truncated && exhausted.is_none() && ran_out {
+                exhausted = Some(mby);
+                if self.partial {
+                    failed = Some(mby);
+                    break;
+                }
+            }
+
+            if let Some(ref mut progress) = self.progress {
+                progress((mby + 1) as f32 / f32::from(self.mbheight));
+            }
+        }
+
+        if let Some(mby) = failed {
+            // The intact rows are filtered as usual, but not across the edge to the failed one.
+            let mbwidth = self.mbwidth as usize;
+            self.filters.truncate(mby * mbwidth);
+            self.filters.resize(mbwidth * self.mbheight as usize, FilterParameters::default());
         }
 
-        Ok(&self.frame)
+        if self.loop_filter_enabled() {
+            self.loop_filter();
+        }
+        self.crop_planes();
+
+        let height = self.frame.height;
+        let rows = |mby: usize| cmp::min(16 * mby, usize::from(height)) as u16;
+        self.intact_rows = match (failed, exhausted) {
+            (Some(a), Some(b)) => rows(cmp::min(a, b)),
+            (Some(mby), None) | (None, Some(mby)) => rows(mby),
+            (None, None) => height,
+        };
+        if let Some(mby) = failed {
+            self.frame.fill_from_row(usize::from(rows(mby)));
+            self.salvaged = true;
+        }
+        self.frame.debug_check_planes();
+
+        Ok(())
     }
 }
 
+/// Sets the length of a plane to `size` zeroes without shrinking its allocation.
+fn resize_plane(plane: &mut Vec<u8>, size: usize) {
+    plane.clear();
+    plane.resize(size, 0);
+}
+
 impl LumaMode {
     fn from_i8(val: i8) -> Option<Self> {
         Some(match val {
@@ -1638,6 +2225,13 @@ fn init_top_macroblocks(width: usize) -> Vec<MacroBlock> {
     vec![mb; mb_width]
 }
 
+/// Returns the prediction workspace of a luma macroblock, with the reconstructed pixels above
+/// and to the left of it in the first row and column.
+///
+/// The 4x4 sub-blocks of the right column have no reconstructed pixels above and to the right of
+/// them below the first row, so they all use the 4 pixels above and to the right of the
+/// macroblock, Section 12.3. At the right edge of the image these replicate the last pixel above
+/// the macroblock, and in the first row of macroblocks they are 127 like the row above.
 fn create_border(mbx: usize, mby: usize, mbw: usize, top: &[u8], left: &[u8]) -> [u8; 357] {
     let stride = 1usize + 16 + 4;
     let mut ws = [0u8; (1 + 16) * (1 + 16 + 4)];
@@ -1695,6 +2289,45 @@ fn create_border(mbx: usize, mby: usize, mbw: usize, top: &[u8], left: &[u8]) ->
     ws
 }
 
+fn create_border_chroma(mbx: usize, mby: usize, top: &[u8], left: &[u8]) -> [u8; 81] {
+    let stride = 1usize + 8;
+    let mut ws = [0u8; (1 + 8) * (1 + 8)];
+
+    // A
+    {
+        let above = &mut ws[1..stride];
+        if mby == 0 {
+            for above in above.iter_mut() {
+                *above = 127;
+            }
+        } else {
+            above.copy_from_slice(&top[mbx * 8..mbx * 8 + 8]);
+        }
+    }
+
+    // L
+    if mbx == 0 {
+        for i in 0usize..8 {
+            ws[(i + 1) * stride] = 129;
+        }
+    } else {
+        for i in 0usize..8 {
+            ws[(i + 1) * stride] = left[i + 1];
+        }
+    }
+
+    // P
+    ws[0] = if mby == 0 {
+        127
+    } else if mbx == 0 {
+        129
+    } else {
+        left[0]
+    };
+
+    ws
+}
+
 fn avg3(left: u8, this: u8, right: u8) -> u8 {
     let avg = (u16::from(left) + 2 * u16::from(this) + u16::from(right) + 2) >> 2;
     avg as u8
@@ -1717,6 +2350,186 @@ fn add_residue(pblock: &mut [u8], rblock: &[i32; 16], y0: usize, x0: usize, stri
     }
 }
 
+/// Filters the left, top and inner edges of a macroblock of one plane, Section 15.2 to 15.3
+///
+/// `size` is the width of the macroblock in the plane, its edges between subblocks are spaced by
+/// four pixels.
+fn filter_macroblock(
+    plane: &mut [u8],
+    stride: usize,
+    size: usize,
+    mbx: usize,
+    mby: usize,
+    params: FilterParameters,
+    simple: bool,
+) {
+    let mbedge_limit = (params.level + 2) * 2 + params.interior_limit;
+    let sub_bedge_limit = params.level * 2 + params.interior_limit;
+    let origin = mby * size * stride + mbx * size;
+
+    let filter_edge = |plane: &mut [u8], point: usize, step: usize, macroblock_edge: bool| {
+        if simple {
+            let limit = if macroblock_edge { mbedge_limit } else { sub_bedge_limit };
+            simple_segment(limit, plane, point, step);
+        } else if macroblock_edge {
+            macroblock_filter(params, mbedge_limit, plane, point, step);
+        } else {
+            subblock_filter(params, sub_bedge_limit, plane, point, step);
+        }
+    };
+
+    // Vertical edges are filtered along the rows, starting with the edge to the left macroblock.
+    if mbx > 0 {
+        for y in 0..size {
+            filter_edge(plane, origin + y * stride, 1, true);
+        }
+    }
+
+    if params.inner {
+        for x in (4..size).step_by(4) {
+            for y in 0..size {
+                filter_edge(plane, origin + y * stride + x, 1, false);
+            }
+        }
+    }
+
+    // Horizontal edges are filtered along the columns.
+    if mby > 0 {
+        for x in 0..size {
+            filter_edge(plane, origin + x, stride, true);
+        }
+    }
+
+    if params.inner {
+        for y in (4..size).step_by(4) {
+            for x in 0..size {
+                filter_edge(plane, origin + y * stride + x, stride, false);
+            }
+        }
+    }
+}
+
+// The filters take the index of the first pixel after the edge and the distance between the
+// pixels across it, with the arithmetic of Section 15.2 on signed bytes.
+fn c(v: i32) -> i32 {
+    clamp(v, -128, 127)
+}
+
+fn u2s(v: u8) -> i32 {
+    i32::from(v) - 128
+}
+
+fn s2u(v: i32) -> u8 {
+    (c(v) + 128) as u8
+}
+
+fn diff(a: u8, b: u8) -> u8 {
+    cmp::max(a, b) - cmp::min(a, b)
+}
+
+fn common_adjust(use_outer_taps: bool, a: &mut [u8], point: usize, step: usize) -> i32 {
+    let p1 = u2s(a[point - 2 * step]);
+    let p0 = u2s(a[point - step]);
+    let q0 = u2s(a[point]);
+    let q1 = u2s(a[point + step]);
+
+    let outer = if use_outer_taps { c(p1 - q1) } else { 0 };
+    let base = c(outer + 3 * (q0 - p0));
+
+    // Divide by 8, with `b` balancing the rounding when the fraction is exactly 1/2.
+    let b = c(base + 3) >> 3;
+    let base = c(base + 4) >> 3;
+
+    a[point] = s2u(q0 - base);
+    a[point - step] = s2u(p0 + b);
+
+    base
+}
+
+fn simple_threshold(edge_limit: u8, a: &[u8], point: usize, step: usize) -> bool {
+    let p1 = a[point - 2 * step];
+    let p0 = a[point - step];
+    let q0 = a[point];
+    let q1 = a[point + step];
+
+    i32::from(diff(p0, q0)) * 2 + i32::from(diff(p1, q1) / 2) <= i32::from(edge_limit)
+}
+
+fn should_filter(interior_limit: u8, edge_limit: u8, a: &[u8], point: usize, step: usize) -> bool {
+    let p3 = a[point - 4 * step];
+    let p2 = a[point - 3 * step];
+    let p1 = a[point - 2 * step];
+    let p0 = a[point - step];
+    let q0 = a[point];
+    let q1 = a[point + step];
+    let q2 = a[point + 2 * step];
+    let q3 = a[point + 3 * step];
+
+    simple_threshold(edge_limit, a, point, step)
+        && diff(p3, p2) <= interior_limit
+        && diff(p2, p1) <= interior_limit
+        && diff(p1, p0) <= interior_limit
+        && diff(q3, q2) <= interior_limit
+        && diff(q2, q1) <= interior_limit
+        && diff(q1, q0) <= interior_limit
+}
+
+fn high_edge_variance(threshold: u8, a: &[u8], point: usize, step: usize) -> bool {
+    diff(a[point - 2 * step], a[point - step]) > threshold
+        || diff(a[point + step], a[point]) > threshold
+}
+
+fn simple_segment(edge_limit: u8, a: &mut [u8], point: usize, step: usize) {
+    if simple_threshold(edge_limit, a, point, step) {
+        common_adjust(true, a, point, step);
+    }
+}
+
+fn subblock_filter(params: FilterParameters, edge_limit: u8, a: &mut [u8], point: usize, step: usize) {
+    if should_filter(params.interior_limit, edge_limit, a, point, step) {
+        let p1 = u2s(a[point - 2 * step]);
+        let q1 = u2s(a[point + step]);
+
+        let hev = high_edge_variance(params.hev_threshold, a, point, step);
+        let adjustment = (common_adjust(hev, a, point, step) + 1) >> 1;
+
+        if !hev {
+            a[point + step] = s2u(q1 - adjustment);
+            a[point - 2 * step] = s2u(p1 + adjustment);
+        }
+    }
+}
+
+fn macroblock_filter(params: FilterParameters, edge_limit: u8, a: &mut [u8], point: usize, step: usize) {
+    if should_filter(params.interior_limit, edge_limit, a, point, step) {
+        if high_edge_variance(params.hev_threshold, a, point, step) {
+            common_adjust(true, a, point, step);
+            return;
+        }
+
+        let p2 = u2s(a[point - 3 * step]);
+        let p1 = u2s(a[point - 2 * step]);
+        let p0 = u2s(a[point - step]);
+        let q0 = u2s(a[point]);
+        let q1 = u2s(a[point + step]);
+        let q2 = u2s(a[point + 2 * step]);
+
+        let w = c(c(p1 - q1) + 3 * (q0 - p0));
+
+        let adjustment = c((27 * w + 63) >> 7);
+        a[point] = s2u(q0 - adjustment);
+        a[point - step] = s2u(p0 + adjustment);
+
+        let adjustment = c((18 * w + 63) >> 7);
+        a[point + step] = s2u(q1 - adjustment);
+        a[point - 2 * step] = s2u(p1 + adjustment);
+
+        let adjustment = c((9 * w + 63) >> 7);
+        a[point + 2 * step] = s2u(q2 - adjustment);
+        a[point - 3 * step] = s2u(p2 + adjustment);
+    }
+}
+
 fn predict_4x4(ws: &mut [u8], stride: usize, modes: &[IntraMode], resdata: &[i32]) {
     for sby in 0usize..4 {
         for sbx in 0usize..4 {
@@ -2020,8 +2833,14 @@ mod test {
     #[cfg(feature = "benchmarks")]
     extern crate test;
     use super::{top_pixels, edge_pixels, avg2, avg3, predict_bvepred, predict_brdpred, predict_bldpred, predict_bhepred, add_residue};
-    #[cfg(feature = "benchmarks")]
-    use super::{IntraMode, predict_4x4};
+    use super::{Vp8Decoder, Frame, Segment, FilterParameters, macroblock_filter, subblock_filter, simple_segment};
+    use super::yuv::yuv_to_rgb;
+    use super::{Prob, COEFF_PROBS, COEFF_UPDATE_PROBS, COEFF_BANDS, DCT_TOKEN_TREE, DCT_1, DCT_EOB, NUM_DCT_TOKENS};
+    use super::{DC_PRED, KEYFRAME_YMODE_TREE, KEYFRAME_YMODE_PROBS, KEYFRAME_UV_MODE_TREE, KEYFRAME_UV_MODE_PROBS};
+    use super::{ImageError, ReferenceUpdates};
+    use super::{IntraMode, create_border, predict_4x4};
+    use std::io::Cursor;
+    use std::sync::mpsc;
     #[cfg(feature = "benchmarks")]
     use test::{Bencher, black_box};
 
@@ -2122,6 +2941,504 @@ mod test {
         });
     }
 
+    /// Strips the RIFF container from a simple lossy file, returning the raw `VP8 ` payload.
+    fn vp8_payload(path: &str) -> Vec<u8> {
+        let data = std::fs::read(path).unwrap();
+        assert_eq!(&data[12..16], b"VP8 ");
+        data[20..].to_vec()
+    }
+
+    #[test]
+    fn test_planes() {
+        let data = vp8_payload("tests/images/webp/images/simple-rgb.webp");
+        let mut decoder = Vp8Decoder::new(Cursor::new(data));
+        let frame = decoder.decode_frame().unwrap();
+
+        let (y, width, height, stride) = frame.y_plane();
+        assert_eq!((width, height, stride), (100, 100, 100));
+        assert_eq!(y.len(), 100 * 100);
+
+        for &(plane, width, height, stride) in &[frame.u_plane(), frame.v_plane()] {
+            assert_eq!((width, height, stride), (50, 50, 50));
+            assert_eq!(plane.len(), 50 * 50);
+        }
+    }
+
+    #[test]
+    fn test_gray_chroma_is_neutral() {
+        let data = vp8_payload("tests/images/webp/images/simple-gray.webp");
+        let mut decoder = Vp8Decoder::new(Cursor::new(data));
+        let frame = decoder.decode_frame().unwrap();
+
+        let (u, ..) = frame.u_plane();
+        let (v, ..) = frame.v_plane();
+        for &sample in u.iter().chain(v) {
+            assert!(sample >= 126 && sample <= 130, "chroma sample {} in a gray image", sample);
+        }
+    }
+
+    #[test]
+    fn test_version_selects_loop_filter() {
+        let data = vp8_payload("tests/images/webp/images/simple-rgb.webp");
+        let filtered = Vp8Decoder::new(Cursor::new(&data)).decode_frame().unwrap();
+
+        // Version 3 uses neither a reconstruction nor a loop filter.
+        let mut data = data;
+        data[0] |= 3 << 1;
+        let unfiltered = Vp8Decoder::new(Cursor::new(&data)).decode_frame().unwrap();
+
+        assert_eq!(filtered.version, 0);
+        assert_eq!(unfiltered.version, 3);
+        assert_ne!(filtered.ybuf, unfiltered.ybuf);
+        assert_ne!(filtered.ubuf, unfiltered.ubuf);
+
+        data[0] |= 4 << 1;
+        assert!(Vp8Decoder::new(Cursor::new(&data)).decode_frame().is_err());
+    }
+
+    #[test]
+    fn test_loop_filter_smooths_edges() {
+        let params = FilterParameters { level: 20, interior_limit: 5, hev_threshold: 1, inner: true };
+
+        // A smooth step across the edge is softened, a flat row is left untouched.
+        let mut step = [60u8, 60, 60, 60, 70, 70, 70, 70];
+        macroblock_filter(params, 45, &mut step, 4, 1);
+        assert_eq!(step, [60, 61, 63, 64, 66, 67, 69, 70]);
+
+        let mut flat = [60u8; 8];
+        subblock_filter(params, 45, &mut flat, 4, 1);
+        assert_eq!(flat, [60u8; 8]);
+
+        // Edges exceeding the limit are considered to be part of the image.
+        let mut step = [0u8, 0, 0, 0, 200, 200, 200, 200];
+        simple_segment(45, &mut step, 4, 1);
+        assert_eq!(step, [0, 0, 0, 0, 200, 200, 200, 200]);
+    }
+
+    #[test]
+    fn test_progress_callback() {
+        let data = vp8_payload("tests/images/webp/images/simple-rgb.webp");
+        let (sender, receiver) = mpsc::channel();
+
+        let mut decoder = Vp8Decoder::new(Cursor::new(data));
+        decoder.set_progress_callback(move |fraction| sender.send(fraction).unwrap());
+        decoder.decode_frame().unwrap();
+
+        // One report for each of the 7 rows of the 100 pixels high image.
+        let reports: Vec<f32> = receiver.try_iter().collect();
+        assert_eq!(reports.len(), 7);
+        assert_eq!(reports[0], 1.0 / 7.0);
+        assert_eq!(reports[6], 1.0);
+        assert!(reports.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn test_fill_rgb_odd_dimensions() {
+        let (width, height) = (101, 3);
+        let (chroma_width, chroma_height) = (51, 2);
+
+        // Every chroma column except the last is neutral, and so is every row except the last.
+        let mut ubuf = vec![128u8; chroma_width * chroma_height];
+        ubuf[chroma_width - 1] = 240;
+        let mut vbuf = vec![128u8; chroma_width * chroma_height];
+        for sample in &mut vbuf[chroma_width..] {
+            *sample = 240;
+        }
+
+        let frame = Frame {
+            width: width as u16,
+            height: height as u16,
+            ybuf: vec![128; width * height],
+            ubuf,
+            vbuf,
+            ..Frame::default()
+        };
+        let mut rgb = vec![0; 3 * width * height];
+        frame.fill_rgb(&mut rgb);
+
+        let pixel = |x: usize, y: usize| &rgb[3 * (x + y * width)..][..3];
+        let neutral = yuv_to_rgb(128, 128, 128);
+        assert_eq!(pixel(width - 3, 0), neutral);
+        assert_eq!(pixel(width - 2, 0), neutral);
+        assert_eq!(pixel(width - 1, 0), yuv_to_rgb(128, 240, 128));
+        assert_eq!(pixel(width - 1, 1), yuv_to_rgb(128, 240, 128));
+        assert_eq!(pixel(0, 1), neutral);
+        assert_eq!(pixel(0, 2), yuv_to_rgb(128, 128, 240));
+        assert_eq!(pixel(width - 1, 2), yuv_to_rgb(128, 128, 240));
+    }
+
+    fn quantizers(index: i32, deltas: [i32; 5]) -> [i16; 6] {
+        let mut segment = Segment::default();
+        segment.set_quantizers(index, deltas);
+        [segment.ydc, segment.yac, segment.y2dc, segment.y2ac, segment.uvdc, segment.uvac]
+    }
+
+    #[test]
+    fn test_quantizer_extremes() {
+        let lowest = [4, 4, 8, 8, 4, 4];
+        let highest = [157, 284, 314, 440, 132, 284];
+
+        assert_eq!(quantizers(0, [0; 5]), lowest);
+        assert_eq!(quantizers(127, [0; 5]), highest);
+
+        // Deltas pointing out of the tables are clamped to their ends.
+        assert_eq!(quantizers(0, [-15; 5]), lowest);
+        assert_eq!(quantizers(127, [15; 5]), highest);
+
+        // So are the indices of segments, before any delta is applied.
+        assert_eq!(quantizers(-40, [0; 5]), lowest);
+        assert_eq!(quantizers(200, [0; 5]), highest);
+        assert_eq!(quantizers(200, [-15; 5]), quantizers(127, [-15; 5]));
+        assert_eq!(quantizers(-40, [15; 5]), quantizers(0, [15; 5]));
+    }
+
+    /// The boolean encoder of Section 7.3, to craft frames the test images don't cover
+    struct BoolWriter {
+        output: Vec<u8>,
+        range: u32,
+        bottom: u32,
+        bit_count: i32,
+    }
+
+    impl BoolWriter {
+        fn new() -> BoolWriter {
+            BoolWriter { output: Vec::new(), range: 255, bottom: 0, bit_count: 24 }
+        }
+
+        fn add_one_to_output(&mut self) {
+            for byte in self.output.iter_mut().rev() {
+                if *byte == 255 {
+                    *byte = 0;
+                } else {
+                    *byte += 1;
+                    break;
+                }
+            }
+        }
+
+        fn write_bool(&mut self, probability: Prob, value: bool) {
+            let split = 1 + (((self.range - 1) * u32::from(probability)) >> 8);
+
+            if value {
+                self.bottom = self.bottom.wrapping_add(split);
+                self.range -= split;
+            } else {
+                self.range = split;
+            }
+
+            while self.range < 128 {
+                self.range <<= 1;
+
+                if self.bottom & (1 << 31) != 0 {
+                    self.add_one_to_output();
+                }
+
+                self.bottom <<= 1;
+                self.bit_count -= 1;
+
+                if self.bit_count == 0 {
+                    self.output.push((self.bottom >> 24) as u8);
+                    self.bottom &= (1 << 24) - 1;
+                    self.bit_count = 8;
+                }
+            }
+        }
+
+        fn write_literal(&mut self, n: u8, value: u8) {
+            for bit in (0..n).rev() {
+                self.write_bool(128, (value >> bit) & 1 == 1);
+            }
+        }
+
+        /// Writes the branches leading to the leaf `value` of the tree.
+        fn write_with_tree(&mut self, tree: &[i8], probs: &[Prob], value: i8) {
+            fn path(tree: &[i8], index: usize, value: i8, branches: &mut Vec<(usize, bool)>) -> bool {
+                for &branch in &[false, true] {
+                    let next = tree[index + branch as usize];
+                    branches.push((index >> 1, branch));
+
+                    if (next <= 0 && -next == value) || (next > 0 && path(tree, next as usize, value, branches)) {
+                        return true;
+                    }
+
+                    branches.pop();
+                }
+
+                false
+            }
+
+            let mut branches = Vec::new();
+            assert!(path(tree, 0, value, &mut branches), "{} is not a leaf of the tree", value);
+            for (prob, branch) in branches {
+                self.write_bool(probs[prob], branch);
+            }
+        }
+
+        fn finish(mut self) -> Vec<u8> {
+            for _ in 0..32 {
+                self.write_bool(128, false);
+            }
+
+            self.output
+        }
+    }
+
+    /// Encodes a keyframe of two macroblocks next to each other, using `DC_PRED` for both and
+    /// the coefficient skip flag for the first. The second codes a single Y2 DC coefficient.
+    fn skipped_macroblock_frame() -> Vec<u8> {
+        coefficient_update_frame(&[])
+    }
+
+    /// Encodes the frame of `skipped_macroblock_frame` with updates of the coefficient token
+    /// probabilities, given as their index into the table and new value, used for the residue.
+    fn coefficient_update_frame(updates: &[([usize; 4], Prob)]) -> Vec<u8> {
+        let mut probs = COEFF_PROBS;
+        for &([i, j, k, t], prob) in updates {
+            probs[i][j][k][t] = prob;
+        }
+
+        let mut header = BoolWriter::new();
+        // Color space and clamping type
+        header.write_literal(2, 0);
+        // No segmentation
+        header.write_literal(1, 0);
+        // The filter type, level and sharpness, which disable the loop filter, without adjustments
+        header.write_literal(1, 0);
+        header.write_literal(6, 0);
+        header.write_literal(3, 0);
+        header.write_literal(1, 0);
+        // One partition
+        header.write_literal(2, 0);
+        // The quantizer index, without deltas
+        header.write_literal(7, 60);
+        header.write_literal(5, 0);
+        // Refresh entropy probs
+        header.write_literal(1, 0);
+        for (i, is) in COEFF_UPDATE_PROBS.iter().enumerate() {
+            for (j, js) in is.iter().enumerate() {
+                for (k, ks) in js.iter().enumerate() {
+                    for (t, &prob) in ks.iter().enumerate() {
+                        let update = updates.iter().find(|update| update.0 == [i, j, k, t]);
+                        header.write_bool(prob, update.is_some());
+                        if let Some(&(_, value)) = update {
+                            header.write_literal(8, value);
+                        }
+                    }
+                }
+            }
+        }
+
+        // `mb_no_skip_coeff` and the probability of a macroblock not being skipped
+        header.write_literal(1, 1);
+        header.write_literal(8, 128);
+
+        for &skip in &[true, false] {
+            header.write_bool(128, skip);
+            header.write_with_tree(&KEYFRAME_YMODE_TREE, &KEYFRAME_YMODE_PROBS, DC_PRED);
+            header.write_with_tree(&KEYFRAME_UV_MODE_TREE, &KEYFRAME_UV_MODE_PROBS, DC_PRED);
+        }
+        let header = header.finish();
+
+        let mut residue = BoolWriter::new();
+        // A positive one as the Y2 DC coefficient, the end of block follows with complexity 1.
+        residue.write_with_tree(&DCT_TOKEN_TREE, &probs[1][0][0], DCT_1);
+        residue.write_bool(128, false);
+        residue.write_with_tree(&DCT_TOKEN_TREE, &probs[1][COEFF_BANDS[1] as usize][1], DCT_EOB);
+        // All luma and chroma blocks are empty.
+        for _ in 0..16 {
+            residue.write_with_tree(&DCT_TOKEN_TREE, &probs[0][COEFF_BANDS[1] as usize][0], DCT_EOB);
+        }
+        for _ in 0..8 {
+            residue.write_with_tree(&DCT_TOKEN_TREE, &probs[2][0][0], DCT_EOB);
+        }
+        let residue = residue.finish();
+
+        let tag = (header.len() as u32) << 5 | 1 << 4;
+        let mut data = vec![tag as u8, (tag >> 8) as u8, (tag >> 16) as u8, 0x9d, 0x01, 0x2a, 32, 0, 16, 0];
+        data.extend_from_slice(&header);
+        data.extend_from_slice(&residue);
+        data
+    }
+
+    #[test]
+    fn test_skipped_macroblocks() {
+        let data = skipped_macroblock_frame();
+        let mut decoder = Vp8Decoder::new(Cursor::new(data));
+        let frame = decoder.decode_frame().unwrap();
+
+        // The skipped macroblock is its prediction alone, while the residue belongs to the
+        // second: the dequantized Y2 DC of 102 adds 2 to every luma sample.
+        let (y, ..) = frame.y_plane();
+        for row in y.chunks(32) {
+            assert_eq!(&row[..16], &[128u8; 16][..]);
+            assert_eq!(&row[16..], &[130u8; 16][..]);
+        }
+
+        let (u, ..) = frame.u_plane();
+        let (v, ..) = frame.v_plane();
+        assert!(u.iter().chain(v).all(|&sample| sample == 128));
+    }
+
+    #[test]
+    fn test_decode_frame_into() {
+        let data = vp8_payload("tests/images/webp/images/simple-rgb.webp");
+        let expected = Vp8Decoder::new(Cursor::new(&data)).decode_frame().unwrap();
+
+        // A larger frame lends its allocations to the smaller one decoded into it.
+        let mut frame = Frame::default();
+        Vp8Decoder::new(Cursor::new(skipped_macroblock_frame())).decode_frame_into(&mut frame).unwrap();
+        frame.ybuf.reserve(100 * 100);
+        let ybuf = frame.ybuf.as_ptr();
+
+        Vp8Decoder::new(Cursor::new(&data)).decode_frame_into(&mut frame).unwrap();
+        assert_eq!(frame.ybuf.as_ptr(), ybuf);
+        assert_eq!((frame.width, frame.height), (expected.width, expected.height));
+        assert_eq!(frame.ybuf, expected.ybuf);
+        assert_eq!(frame.ubuf, expected.ubuf);
+        assert_eq!(frame.vbuf, expected.vbuf);
+    }
+
+    #[test]
+    fn test_inconsistent_planes() {
+        let data = vp8_payload("tests/images/webp/images/simple-rgb.webp");
+        let frame = Vp8Decoder::new(Cursor::new(data)).decode_frame().unwrap();
+        assert!(frame.check_planes().is_ok());
+
+        // Odd dimensions round the chroma planes up.
+        let mut odd = Frame { width: 3, height: 1, ..Frame::default() };
+        odd.ybuf = vec![0; 3];
+        odd.ubuf = vec![0; 2];
+        odd.vbuf = vec![0; 2];
+        assert!(odd.check_planes().is_ok());
+
+        let mut short = frame.clone();
+        short.ubuf.pop();
+        assert!(short.check_planes().is_err());
+
+        let mut resized = frame;
+        resized.width = 102;
+        match resized.check_planes() {
+            Err(ImageError::Decoding(_)) => {}
+            _ => panic!("expected planes of another size to be an error"),
+        }
+    }
+
+    #[test]
+    fn test_coefficient_probability_updates() {
+        // Update every probability of the Y2 plane and those coding the empty blocks, so that
+        // the residue is only decoded as written if all updates are applied.
+        let mut updates = Vec::new();
+        for j in 0..8 {
+            for k in 0..3 {
+                for t in 0..NUM_DCT_TOKENS - 1 {
+                    updates.push(([1, j, k, t], (1 + (j * 33 + k * 11 + t) * 37 % 255) as u8));
+                }
+            }
+        }
+        updates.push(([0, COEFF_BANDS[1] as usize, 0, 0], 3));
+        updates.push(([2, 0, 0, 0], 250));
+
+        let mut decoder = Vp8Decoder::new(Cursor::new(coefficient_update_frame(&updates)));
+        let frame = decoder.decode_frame().unwrap();
+        let (y, ..) = frame.y_plane();
+        for row in y.chunks(32) {
+            assert_eq!(&row[..16], &[128u8; 16][..]);
+            assert_eq!(&row[16..], &[130u8; 16][..]);
+        }
+
+        let mut expected = COEFF_PROBS;
+        for &([i, j, k, t], prob) in &updates {
+            expected[i][j][k][t] = prob;
+        }
+        assert!(decoder.token_probs.iter().eq(expected.iter()));
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "inconsistent planes")]
+    fn test_debug_check_planes() {
+        let data = vp8_payload("tests/images/webp/images/simple-rgb.webp");
+        let mut frame = Vp8Decoder::new(Cursor::new(data)).decode_frame().unwrap();
+        frame.debug_check_planes();
+
+        frame.vbuf.push(0);
+        frame.debug_check_planes();
+    }
+
+    /// Encodes the first partition of an inter frame up to its reference frame updates, which
+    /// are given as `(bits, value)` pairs.
+    fn inter_frame(updates: &[(u8, u8)]) -> Vec<u8> {
+        let mut header = BoolWriter::new();
+        // No segmentation, a simple loop filter of level 0 without adjustments, one partition.
+        header.write_literal(1, 0);
+        header.write_literal(1, 1);
+        header.write_literal(6, 0);
+        header.write_literal(3, 0);
+        header.write_literal(1, 0);
+        header.write_literal(2, 0);
+        // A quantizer index of 0 without deltas.
+        header.write_literal(7, 0);
+        header.write_literal(5, 0);
+        for &(bits, value) in updates {
+            header.write_literal(bits, value);
+        }
+        let header = header.finish();
+
+        let tag = (header.len() as u32) << 5 | 1 << 4 | 1;
+        let mut data = vec![tag as u8, (tag >> 8) as u8, (tag >> 16) as u8];
+        data.extend_from_slice(&header);
+        // The token partition is never read.
+        data.extend_from_slice(&[0, 0]);
+        data
+    }
+
+    #[test]
+    fn test_reference_updates() {
+        let data = skipped_macroblock_frame();
+        let mut decoder = Vp8Decoder::new(Cursor::new(data));
+        let references = decoder.decode_frame().unwrap().references;
+        assert!(references.refresh_golden_frame && references.refresh_alternate_frame);
+        assert!(references.refresh_last);
+        assert_eq!((references.copy_buffer_to_golden, references.copy_buffer_to_alternate), (0, 0));
+
+        // The golden frame is kept while the last frame is copied to the alternate frame, with
+        // the sign bias of the golden frame set and the probabilities kept.
+        let data = inter_frame(&[(1, 0), (1, 0), (2, 0), (2, 1), (1, 1), (1, 0), (1, 1), (1, 0)]);
+        let mut decoder = Vp8Decoder::new(Cursor::new(data));
+        match decoder.decode() {
+            Err(ImageError::Unsupported(_)) => {}
+            _ => panic!("expected inter frames to be unsupported"),
+        }
+        assert_eq!(decoder.frame.references, ReferenceUpdates {
+            refresh_golden_frame: false,
+            refresh_alternate_frame: false,
+            copy_buffer_to_golden: 0,
+            copy_buffer_to_alternate: 1,
+            sign_bias_golden: true,
+            sign_bias_alternate: false,
+            refresh_entropy_probs: true,
+            refresh_last: false,
+        });
+
+        let data = inter_frame(&[(1, 0), (1, 1), (2, 3), (1, 0), (1, 0), (1, 0), (1, 0)]);
+        match Vp8Decoder::new(Cursor::new(data)).decode_frame() {
+            Err(ImageError::Decoding(_)) => {}
+            _ => panic!("expected an invalid buffer copy to be a decoding error"),
+        }
+    }
+
+    #[test]
+    fn test_corrupted_start_code() {
+        let mut data = skipped_macroblock_frame();
+        assert!(Vp8Decoder::new(Cursor::new(&data)).decode_frame().is_ok());
+
+        data[4] = 0x02;
+        match Vp8Decoder::new(Cursor::new(data)).decode_frame() {
+            Err(ImageError::Decoding(_)) => {}
+            _ => panic!("expected a corrupted start code to be a decoding error"),
+        }
+    }
+
     #[test]
     fn test_avg2() {
         for i in 0u8..=255 {
@@ -2319,5 +3636,106 @@ mod test {
         assert_eq!(im[40], avg_4);
     }
 
+    /// A row of fine detail above two macroblocks, followed by pixels past the right edge.
+    fn detailed_top(past_edge: u8) -> Vec<u8> {
+        let mut top: Vec<u8> = (0..32).map(|i| if i % 2 == 0 { 0 } else { 255 }).collect();
+        top.extend_from_slice(&[past_edge; 20]);
+        top
+    }
+
+    #[test]
+    fn test_create_border_above_right() {
+        let top: Vec<u8> = (0..52).collect();
+        let left = [129u8; 17];
+        let stride = 1 + 16 + 4;
+
+        // Inside the image the pixels above and to the right belong to the next macroblock, and
+        // the sub-blocks of the right column in lower rows see the same pixels.
+        let ws = create_border(0, 1, 2, &top, &left);
+        for &row in &[0, 4, 8, 12] {
+            assert_eq!(ws[row * stride + 17..][..4], [16, 17, 18, 19]);
+        }
+
+        // At the right edge the last pixel above the macroblock is replicated.
+        let ws = create_border(1, 1, 2, &top, &left);
+        for &row in &[0, 4, 8, 12] {
+            assert_eq!(ws[row * stride + 17..][..4], [31; 4]);
+        }
+
+        // In the top row there is nothing above, on the edge or not.
+        for &mbx in &[0, 1] {
+            let ws = create_border(mbx, 0, 2, &top, &left);
+            for &row in &[0, 4, 8, 12] {
+                assert_eq!(ws[row * stride + 17..][..4], [127; 4]);
+            }
+            assert_eq!(ws[1..stride], [127; 20]);
+        }
+    }
+
+    #[test]
+    fn test_predict_4x4_right_edge() {
+        let modes = [
+            IntraMode::DC, IntraMode::TM, IntraMode::VE, IntraMode::HE, IntraMode::LD,
+            IntraMode::RD, IntraMode::VR, IntraMode::VL, IntraMode::HD, IntraMode::HU,
+        ];
+        let (left, resdata) = ([40u8; 17], [0i32; 256]);
+        let stride = 1 + 16 + 4;
+
+        for &mode in &modes {
+            // The pixels past the right edge of the image must not affect the prediction.
+            let mut predicted = Vec::new();
+            for &past_edge in &[0, 200] {
+                let mut ws = create_border(1, 1, 2, &detailed_top(past_edge), &left);
+                predict_4x4(&mut ws, stride, &[mode; 16], &resdata);
+                predicted.push(ws.to_vec());
+            }
+            assert!(predicted[0] == predicted[1], "{:?} reads past the right edge", mode);
+
+            if mode == IntraMode::LD {
+                // The top right sub-block extends the last pixel above, 255, to the right.
+                let ws = &predicted[0];
+                let avgs = [128, 128, 191, 255, 255, 255, 255];
+                for y in 0..4 {
+                    assert_eq!(ws[(1 + y) * stride + 13..][..4], avgs[y..y + 4]);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_partial_frames() {
+        let data = vp8_payload("tests/images/webp/images/simple-rgb.webp");
+        let mut decoder = Vp8Decoder::new(Cursor::new(&data));
+        decoder.set_data_len(data.len() as u32);
+        decoder.set_partial(true);
+        let full = decoder.decode_frame().unwrap();
+        assert_eq!(decoder.intact_rows(), 100);
+        assert!(!decoder.is_partial());
+
+        let truncated = &data[..data.len() * 3 / 4];
+        let mut decoder = Vp8Decoder::new(Cursor::new(truncated));
+        decoder.set_data_len(data.len() as u32);
+        decoder.set_partial(true);
+        let frame = decoder.decode_frame().unwrap();
+        assert!(decoder.is_partial());
+
+        // The intact rows are reconstructed exactly, except for the loop filter across the edge
+        // to the rows below, which are filled with gray.
+        let rows = usize::from(decoder.intact_rows());
+        assert!(rows > 0 && rows < 100 && rows % 16 == 0);
+        assert_eq!(frame.ybuf[..(rows - 3) * 100], full.ybuf[..(rows - 3) * 100]);
+        assert_eq!(frame.ubuf[..(rows / 2 - 3) * 50], full.ubuf[..(rows / 2 - 3) * 50]);
+        assert!(frame.ybuf[rows * 100..].iter().all(|&y| y == 128));
+        assert!(frame.vbuf[rows * 25..].iter().all(|&v| v == 128));
+
+        // Otherwise the missing data is decoded as zero bits.
+        let mut decoder = Vp8Decoder::new(Cursor::new(truncated));
+        decoder.set_data_len(data.len() as u32);
+        let frame = decoder.decode_frame().unwrap();
+        assert_eq!(usize::from(decoder.intact_rows()), rows);
+        assert!(!decoder.is_partial());
+        assert!(frame.ybuf[rows * 100..].iter().any(|&y| y != 128));
+    }
+
 }
 
@@ -0,0 +1,402 @@
+//! Conversion of decoded YUV planes to RGB
+//!
+//! The conversion uses the same fixed point arithmetic as libwebp, so that the vectorized
+//! implementations can reproduce the results of the scalar one exactly. On x86 and x86_64 the
+//! fastest implementation supported by the CPU is selected at runtime, falling back to the scalar
+//! version on other architectures. There is no NEON version for aarch64, as its intrinsics are
+//! not stable in the oldest supported compiler.
+
+#[cfg(target_arch = "x86")]
+use std::arch::x86::*;
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::*;
+
+use crate::math::utils::clamp;
+
+/// The filter used to upsample the chroma planes to the resolution of the luma plane.
+///
+/// Each chroma sample is centered on the 2x2 block of pixels it covers, so every pixel lies a
+/// quarter of a chroma sample away from the nearest one in each direction. The filters differ in
+/// how the samples around it are weighted, horizontally and vertically alike. Samples past the
+/// edges of the planes are replaced by the samples at the edge.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UpsampleFilter {
+    /// Uses the nearest chroma sample for every pixel of its block, the default.
+    Point,
+    /// Interpolates linearly between the two nearest chroma samples, with weights of 3/4 and
+    /// 1/4. This matches the fancy upsampling of libwebp.
+    Bilinear,
+    /// Interpolates between the four nearest chroma samples with the Catmull-Rom spline, with
+    /// weights of -9/128, 111/128, 29/128 and -3/128 from the far side to the near side. This
+    /// keeps color edges sharper than the bilinear filter, at the cost of slight ringing.
+    Sharp,
+}
+
+impl Default for UpsampleFilter {
+    fn default() -> UpsampleFilter {
+        UpsampleFilter::Point
+    }
+}
+
+impl UpsampleFilter {
+    /// Returns the weights of the samples before, at, after and two after the nearest one, in
+    /// the direction of the pixel, and the number of fractional bits of the weights.
+    fn taps(self) -> ([i32; 4], u32) {
+        match self {
+            UpsampleFilter::Point => ([0, 1, 0, 0], 0),
+            UpsampleFilter::Bilinear => ([0, 3, 1, 0], 2),
+            UpsampleFilter::Sharp => ([-9, 111, 29, -3], 7),
+        }
+    }
+}
+
+/// Upsamples the chroma plane of `width` by `height` samples for luma row `y`.
+///
+/// Writes one chroma sample per luma sample of the row into `out`.
+pub(crate) fn upsample_row(
+    filter: UpsampleFilter,
+    plane: &[u8],
+    width: usize,
+    height: usize,
+    y: usize,
+    out: &mut [u8],
+) {
+    let (taps, bits) = filter.taps();
+
+    // The filter is separable: filter the rows vertically, then the result horizontally.
+    let rows = neighbours(y, height);
+    let column: Vec<i32> = (0..width)
+        .map(|x| {
+            rows.iter()
+                .zip(&taps)
+                .map(|(&row, &tap)| tap * i32::from(plane[row * width + x]))
+                .sum()
+        })
+        .collect();
+
+    let shift = 2 * bits;
+    for (x, out) in out.iter_mut().enumerate() {
+        let columns = neighbours(x, width);
+        let sum: i32 = columns.iter().zip(&taps).map(|(&x, &tap)| tap * column[x]).sum();
+        *out = clamp((sum + (1 << shift >> 1)) >> shift, 0, 255) as u8;
+    }
+}
+
+/// Returns the indices of the chroma samples weighted by `UpsampleFilter::taps` for luma
+/// sample `i`, clamped to the `len` samples of the plane.
+fn neighbours(i: usize, len: usize) -> [usize; 4] {
+    let nearest = (i / 2) as isize;
+    // Even luma samples lie before the center of their chroma sample, odd ones after it.
+    let step = if i % 2 == 1 { 1 } else { -1 };
+    let at = |offset: isize| clamp(nearest + offset * step, 0, len as isize - 1) as usize;
+    [at(-1), at(0), at(1), at(2)]
+}
+
+/// Converts a row of pixels to interleaved RGB.
+///
+/// `y` holds the luma samples of the row, `u` and `v` the chroma samples where each covers two
+/// horizontally adjacent luma samples. Writes three bytes per luma sample into `rgb`.
+pub(crate) fn yuv_to_rgb_row(y: &[u8], u: &[u8], v: &[u8], rgb: &mut [u8]) {
+    let width = y.len();
+    assert!(u.len() >= (width + 1) / 2 && v.len() >= (width + 1) / 2);
+    assert!(rgb.len() >= 3 * width);
+
+    #[allow(unused_mut)]
+    let mut done = 0;
+
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    {
+        if is_x86_feature_detected!("avx2") {
+            // Safety: the CPU supports avx2 and the slices have been validated above.
+            done = unsafe { yuv_to_rgb_row_avx2(y, u, v, rgb) };
+        } else if is_x86_feature_detected!("sse2") {
+            // Safety: the CPU supports sse2 and the slices have been validated above.
+            done = unsafe { yuv_to_rgb_row_sse2(y, u, v, rgb) };
+        }
+    }
+
+    // The vectorized versions only convert whole blocks of an even number of pixels.
+    yuv_to_rgb_row_scalar(&y[done..], &u[done / 2..], &v[done / 2..], &mut rgb[3 * done..]);
+}
+
+fn yuv_to_rgb_row_scalar(y: &[u8], u: &[u8], v: &[u8], rgb: &mut [u8]) {
+    for (i, (&y, pixel)) in y.iter().zip(rgb.chunks_mut(3)).enumerate() {
+        pixel.copy_from_slice(&yuv_to_rgb(y, u[i / 2], v[i / 2]));
+    }
+}
+
+/// Converts a single pixel.
+///
+/// This is the BT.601 conversion with 14 bits of fixed point precision, where luma is scaled from
+/// the `16..=235` range and chroma from the `16..=240` range to the full range of a byte.
+pub(crate) fn yuv_to_rgb(y: u8, u: u8, v: u8) -> [u8; 3] {
+    fn mult_hi(value: u8, coeff: i32) -> i32 {
+        (i32::from(value) * coeff) >> 8
+    }
+
+    fn clip(value: i32) -> u8 {
+        clamp(value >> 6, 0, 255) as u8
+    }
+
+    let luma = mult_hi(y, 19077);
+    [
+        clip(luma + mult_hi(v, 26149) - 14234),
+        clip(luma - mult_hi(u, 6419) - mult_hi(v, 13320) + 8708),
+        clip(luma + mult_hi(u, 33050) - 17685),
+    ]
+}
+
+/// Writes planar red, green and blue bytes of a block of pixels into `rgb` as interleaved RGB.
+fn interleave(r: &[u8], g: &[u8], b: &[u8], rgb: &mut [u8]) {
+    for (((pixel, &r), &g), &b) in rgb.chunks_mut(3).zip(r).zip(g).zip(b) {
+        pixel[0] = r;
+        pixel[1] = g;
+        pixel[2] = b;
+    }
+}
+
+/// Converts blocks of 8 pixels, returning the number of pixels converted.
+///
+/// # Safety
+///
+/// The CPU must support sse2. `u` and `v` must hold at least half as many samples as `y` and
+/// `rgb` at least three bytes per sample of `y`.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "sse2")]
+unsafe fn yuv_to_rgb_row_sse2(y: &[u8], u: &[u8], v: &[u8], rgb: &mut [u8]) -> usize {
+    let zero = _mm_setzero_si128();
+    let blocks = y.len() / 8;
+
+    let mut r = [0u8; 8];
+    let mut g = [0u8; 8];
+    let mut b = [0u8; 8];
+
+    for i in 0..blocks {
+        // Place each sample in the upper byte of a 16 bit lane, chroma samples twice.
+        let y8 = _mm_loadl_epi64(y.as_ptr().add(8 * i) as *const __m128i);
+        let u4 = _mm_cvtsi32_si128(load_u32(&u[4 * i..]));
+        let v4 = _mm_cvtsi32_si128(load_u32(&v[4 * i..]));
+
+        let y8 = _mm_unpacklo_epi8(zero, y8);
+        let u8 = _mm_unpacklo_epi8(zero, _mm_unpacklo_epi8(u4, u4));
+        let v8 = _mm_unpacklo_epi8(zero, _mm_unpacklo_epi8(v4, v4));
+
+        let luma = _mm_mulhi_epu16(y8, _mm_set1_epi16(19077));
+
+        let r0 = _mm_mulhi_epu16(v8, _mm_set1_epi16(26149));
+        let r1 = _mm_add_epi16(_mm_sub_epi16(luma, _mm_set1_epi16(14234)), r0);
+
+        let g0 = _mm_mulhi_epu16(u8, _mm_set1_epi16(6419));
+        let g1 = _mm_mulhi_epu16(v8, _mm_set1_epi16(13320));
+        let g2 = _mm_sub_epi16(_mm_add_epi16(luma, _mm_set1_epi16(8708)), _mm_add_epi16(g0, g1));
+
+        // The blue channel exceeds the range of signed 16 bit integers, so use saturating
+        // unsigned arithmetic instead. The saturation at 0 coincides with clipping.
+        let b0 = _mm_mulhi_epu16(u8, _mm_set1_epi16(33050u16 as i16));
+        let b1 = _mm_subs_epu16(_mm_adds_epu16(b0, luma), _mm_set1_epi16(17685));
+
+        let r1 = _mm_packus_epi16(_mm_srai_epi16(r1, 6), zero);
+        let g2 = _mm_packus_epi16(_mm_srai_epi16(g2, 6), zero);
+        let b1 = _mm_packus_epi16(_mm_srli_epi16(b1, 6), zero);
+
+        _mm_storel_epi64(r.as_mut_ptr() as *mut __m128i, r1);
+        _mm_storel_epi64(g.as_mut_ptr() as *mut __m128i, g2);
+        _mm_storel_epi64(b.as_mut_ptr() as *mut __m128i, b1);
+
+        interleave(&r, &g, &b, &mut rgb[24 * i..24 * i + 24]);
+    }
+
+    8 * blocks
+}
+
+/// Converts blocks of 16 pixels, returning the number of pixels converted.
+///
+/// # Safety
+///
+/// The CPU must support avx2. `u` and `v` must hold at least half as many samples as `y` and
+/// `rgb` at least three bytes per sample of `y`.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "avx2")]
+unsafe fn yuv_to_rgb_row_avx2(y: &[u8], u: &[u8], v: &[u8], rgb: &mut [u8]) -> usize {
+    let zero = _mm256_setzero_si256();
+    let blocks = y.len() / 16;
+
+    let mut r = [0u8; 16];
+    let mut g = [0u8; 16];
+    let mut b = [0u8; 16];
+
+    for i in 0..blocks {
+        // Place each sample in the upper byte of a 16 bit lane, chroma samples twice.
+        let y16 = _mm_loadu_si128(y.as_ptr().add(16 * i) as *const __m128i);
+        let u8 = _mm_loadl_epi64(u.as_ptr().add(8 * i) as *const __m128i);
+        let v8 = _mm_loadl_epi64(v.as_ptr().add(8 * i) as *const __m128i);
+
+        let y16 = _mm256_slli_epi16(_mm256_cvtepu8_epi16(y16), 8);
+        let u16 = _mm256_slli_epi16(_mm256_cvtepu8_epi16(_mm_unpacklo_epi8(u8, u8)), 8);
+        let v16 = _mm256_slli_epi16(_mm256_cvtepu8_epi16(_mm_unpacklo_epi8(v8, v8)), 8);
+
+        let luma = _mm256_mulhi_epu16(y16, _mm256_set1_epi16(19077));
+
+        let r0 = _mm256_mulhi_epu16(v16, _mm256_set1_epi16(26149));
+        let r1 = _mm256_add_epi16(_mm256_sub_epi16(luma, _mm256_set1_epi16(14234)), r0);
+
+        let g0 = _mm256_mulhi_epu16(u16, _mm256_set1_epi16(6419));
+        let g1 = _mm256_mulhi_epu16(v16, _mm256_set1_epi16(13320));
+        let g2 = _mm256_sub_epi16(
+            _mm256_add_epi16(luma, _mm256_set1_epi16(8708)),
+            _mm256_add_epi16(g0, g1),
+        );
+
+        // See the sse2 version for why this uses unsigned arithmetic.
+        let b0 = _mm256_mulhi_epu16(u16, _mm256_set1_epi16(33050u16 as i16));
+        let b1 = _mm256_subs_epu16(_mm256_adds_epu16(b0, luma), _mm256_set1_epi16(17685));
+
+        // Packing works within each 128 bit half, so gather the low quad words afterwards.
+        let r1 = _mm256_packus_epi16(_mm256_srai_epi16(r1, 6), zero);
+        let g2 = _mm256_packus_epi16(_mm256_srai_epi16(g2, 6), zero);
+        let b1 = _mm256_packus_epi16(_mm256_srli_epi16(b1, 6), zero);
+
+        let r1 = _mm256_castsi256_si128(_mm256_permute4x64_epi64(r1, 0b00_00_10_00));
+        let g2 = _mm256_castsi256_si128(_mm256_permute4x64_epi64(g2, 0b00_00_10_00));
+        let b1 = _mm256_castsi256_si128(_mm256_permute4x64_epi64(b1, 0b00_00_10_00));
+
+        _mm_storeu_si128(r.as_mut_ptr() as *mut __m128i, r1);
+        _mm_storeu_si128(g.as_mut_ptr() as *mut __m128i, g2);
+        _mm_storeu_si128(b.as_mut_ptr() as *mut __m128i, b1);
+
+        interleave(&r, &g, &b, &mut rgb[48 * i..48 * i + 48]);
+    }
+
+    16 * blocks
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn load_u32(bytes: &[u8]) -> i32 {
+    let mut word = [0u8; 4];
+    word.copy_from_slice(&bytes[..4]);
+    i32::from_le_bytes(word)
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "benchmarks")]
+    extern crate test;
+    use super::*;
+    #[cfg(feature = "benchmarks")]
+    use test::{Bencher, black_box};
+
+    /// A row of every luma value for each combination of chroma values.
+    fn sample_rows() -> impl Iterator<Item = (Vec<u8>, Vec<u8>, Vec<u8>)> {
+        let y: Vec<u8> = (0..=255).collect();
+        (0..=255u8).flat_map(move |u| {
+            let y = y.clone();
+            (0..=255u8).map(move |v| (y.clone(), vec![u; 128], vec![v; 128]))
+        })
+    }
+
+    #[test]
+    fn test_reference_values() {
+        assert_eq!(yuv_to_rgb(16, 128, 128), [0, 0, 0]);
+        assert_eq!(yuv_to_rgb(235, 128, 128), [255, 255, 255]);
+        assert_eq!(yuv_to_rgb(128, 128, 128), [130, 130, 130]);
+        assert_eq!(yuv_to_rgb(81, 90, 240), [254, 0, 0]);
+    }
+
+    #[test]
+    fn test_dispatch_matches_scalar() {
+        for (y, u, v) in sample_rows() {
+            let mut scalar = vec![0; 3 * y.len()];
+            let mut dispatched = vec![0; 3 * y.len()];
+            yuv_to_rgb_row_scalar(&y, &u, &v, &mut scalar);
+            yuv_to_rgb_row(&y, &u, &v, &mut dispatched);
+            assert_eq!(scalar, dispatched);
+        }
+    }
+
+    #[test]
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    fn test_simd_matches_scalar() {
+        for (y, u, v) in sample_rows() {
+            let mut scalar = vec![0; 3 * y.len()];
+            yuv_to_rgb_row_scalar(&y, &u, &v, &mut scalar);
+
+            if is_x86_feature_detected!("sse2") {
+                let mut simd = vec![0; 3 * y.len()];
+                assert_eq!(unsafe { yuv_to_rgb_row_sse2(&y, &u, &v, &mut simd) }, y.len());
+                assert_eq!(scalar, simd);
+            }
+
+            if is_x86_feature_detected!("avx2") {
+                let mut simd = vec![0; 3 * y.len()];
+                assert_eq!(unsafe { yuv_to_rgb_row_avx2(&y, &u, &v, &mut simd) }, y.len());
+                assert_eq!(scalar, simd);
+            }
+        }
+    }
+
+    #[test]
+    fn test_upsample_flat_plane() {
+        let plane = [77; 6 * 4];
+        for &filter in &[UpsampleFilter::Point, UpsampleFilter::Bilinear, UpsampleFilter::Sharp] {
+            for y in 0..8 {
+                let mut out = [0; 11];
+                upsample_row(filter, &plane, 6, 4, y, &mut out);
+                assert_eq!(out, [77; 11]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_upsample_taps() {
+        let upsample = |filter, plane: &[u8]| {
+            let mut out = [0; 4];
+            upsample_row(filter, plane, 2, 1, 0, &mut out);
+            out
+        };
+
+        assert_eq!(upsample(UpsampleFilter::Point, &[0, 160]), [0, 0, 160, 160]);
+        assert_eq!(upsample(UpsampleFilter::Bilinear, &[0, 160]), [0, 40, 120, 160]);
+        // The negative taps under- and overshoot next to the edge, the first sample is clamped.
+        assert_eq!(upsample(UpsampleFilter::Sharp, &[0, 160]), [0, 33, 128, 171]);
+    }
+
+    #[test]
+    fn test_odd_remainder() {
+        let y: Vec<u8> = (0..37).map(|i| i * 7).collect();
+        let u: Vec<u8> = (0..19).map(|i| 255 - i * 13).collect();
+        let v: Vec<u8> = (0..19).map(|i| i * 13).collect();
+
+        let mut scalar = vec![0; 3 * y.len()];
+        let mut dispatched = vec![0; 3 * y.len()];
+        yuv_to_rgb_row_scalar(&y, &u, &v, &mut scalar);
+        yuv_to_rgb_row(&y, &u, &v, &mut dispatched);
+        assert_eq!(scalar, dispatched);
+    }
+
+    #[cfg(feature = "benchmarks")]
+    const WIDTH: usize = 4096;
+
+    #[cfg(feature = "benchmarks")]
+    #[bench]
+    fn bench_yuv_to_rgb_row_scalar(b: &mut Bencher) {
+        let y = vec![100u8; WIDTH];
+        let uv = vec![60u8; WIDTH / 2];
+        let mut rgb = vec![0u8; 3 * WIDTH];
+
+        b.iter(|| {
+            yuv_to_rgb_row_scalar(black_box(&y), black_box(&uv), black_box(&uv), &mut rgb);
+        });
+    }
+
+    #[cfg(feature = "benchmarks")]
+    #[bench]
+    fn bench_yuv_to_rgb_row(b: &mut Bencher) {
+        let y = vec![100u8; WIDTH];
+        let uv = vec![60u8; WIDTH / 2];
+        let mut rgb = vec![0u8; 3 * WIDTH];
+
+        b.iter(|| {
+            yuv_to_rgb_row(black_box(&y), black_box(&uv), black_box(&uv), &mut rgb);
+        });
+    }
+}